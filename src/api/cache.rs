@@ -0,0 +1,215 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::header::{HeaderMap, HeaderValue, LINK};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    crypto::{self, EncryptionKey, ENCRYPTED_SUFFIX},
+    io::atomic_write::write_atomic,
+};
+
+/// Entries not read or written for this long are removed by [`prune`].
+pub const MAX_ENTRY_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// On-disk cache of GET responses keyed by URL and token, used to send
+/// conditional requests. GitHub does not count `304 Not Modified` responses
+/// against the rate limit.
+///
+/// With a key, entries are encrypted like the rest of the backup, since
+/// responses may include private repository data.
+#[derive(Debug, Clone)]
+pub struct HttpCache {
+    dir: PathBuf,
+    namespace: String,
+    key: Option<Arc<EncryptionKey>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub link: Option<String>,
+    pub body: String,
+}
+
+impl CachedResponse {
+    /// Headers replayed to callers on a cache hit; only `Link` is needed for
+    /// pagination.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = self
+            .link
+            .as_deref()
+            .and_then(|link| HeaderValue::from_str(link).ok())
+        {
+            headers.insert(LINK, value);
+        }
+        headers
+    }
+}
+
+impl HttpCache {
+    /// Entries are partitioned by token so responses that include private
+    /// data are never replayed for a different credential.
+    pub fn new(dir: &Path, token: Option<&str>, key: Option<Arc<EncryptionKey>>) -> Self {
+        let namespace = format!("{:x}", Sha256::digest(token.unwrap_or_default()));
+        Self {
+            dir: dir.to_path_buf(),
+            namespace,
+            key,
+        }
+    }
+
+    /// Returns the entry for `url` and marks it as used, so [`prune`] keeps
+    /// it.
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        let path = self.path_for(url);
+        let mut bytes = fs::read(&path).ok()?;
+        if let Some(key) = &self.key {
+            bytes = crypto::decrypt(key, &bytes).ok()?;
+        }
+        let entry = serde_json::from_slice::<CachedResponse>(&bytes)
+            .ok()
+            .filter(|entry| entry.url == url)?;
+
+        let _ = fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(entry)
+    }
+
+    pub fn store(&self, entry: &CachedResponse) -> io::Result<()> {
+        let mut bytes = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        if let Some(key) = &self.key {
+            bytes = crypto::encrypt(key, &bytes);
+        }
+        write_atomic(&self.path_for(&entry.url), &bytes)
+    }
+
+    fn path_for(&self, url: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.namespace);
+        hasher.update(url);
+        let suffix = if self.key.is_some() {
+            ENCRYPTED_SUFFIX
+        } else {
+            ""
+        };
+        self.dir
+            .join(format!("{:x}.json{suffix}", hasher.finalize()))
+    }
+}
+
+/// Removes cache entries unused for [`MAX_ENTRY_AGE`] and, when `encrypted`,
+/// every plaintext entry written before encryption was turned on. Returns the
+/// number of entries removed.
+pub fn prune(dir: &Path, encrypted: bool, now: SystemTime) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let plaintext = name.ends_with(".json");
+        if !plaintext && !name.ends_with(&format!(".json{ENCRYPTED_SUFFIX}")) {
+            continue;
+        }
+
+        let unused = entry
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > MAX_ENTRY_AGE);
+        if unused || (encrypted && plaintext) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(dir: &Path) -> Arc<EncryptionKey> {
+        let path = dir.join("key");
+        fs::write(&path, "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+        Arc::new(EncryptionKey::from_file(&path).unwrap())
+    }
+
+    fn entry(url: &str) -> CachedResponse {
+        CachedResponse {
+            url: url.to_string(),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            link: None,
+            body: r#"[{"name":"private-repo"}]"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn encrypts_entries_with_a_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(
+            &dir.path().join("http"),
+            Some("token"),
+            Some(key(dir.path())),
+        );
+        let url = "https://api.github.com/user/repos";
+
+        cache.store(&entry(url)).unwrap();
+        let path = cache.path_for(url);
+        assert!(path.to_string_lossy().ends_with(".json.enc"));
+        let stored = fs::read(&path).unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains("private-repo"));
+        assert_eq!(cache.get(url).unwrap().body, entry(url).body);
+
+        let plaintext = HttpCache::new(&dir.path().join("http"), Some("token"), None);
+        assert!(plaintext.get(url).is_none());
+    }
+
+    #[test]
+    fn prunes_unused_and_plaintext_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("http");
+        let plaintext = HttpCache::new(&cache_dir, None, None);
+        let encrypted = HttpCache::new(&cache_dir, None, Some(key(dir.path())));
+        for url in ["https://host/old", "https://host/fresh"] {
+            encrypted.store(&entry(url)).unwrap();
+        }
+        plaintext.store(&entry("https://host/plain")).unwrap();
+
+        let now = SystemTime::now();
+        fs::File::options()
+            .write(true)
+            .open(encrypted.path_for("https://host/old"))
+            .unwrap()
+            .set_modified(now - MAX_ENTRY_AGE - Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(prune(&cache_dir, false, now).unwrap(), 1);
+        assert!(encrypted.get("https://host/old").is_none());
+        assert!(encrypted.get("https://host/fresh").is_some());
+        assert!(plaintext.get("https://host/plain").is_some());
+
+        assert_eq!(prune(&cache_dir, true, now).unwrap(), 1);
+        assert!(plaintext.get("https://host/plain").is_none());
+        assert!(encrypted.get("https://host/fresh").is_some());
+        assert_eq!(prune(&dir.path().join("missing"), true, now).unwrap(), 0);
+    }
+}
@@ -1,11 +1,27 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION, LINK, USER_AGENT};
+use futures::{future::Either, stream, Stream, StreamExt, TryStreamExt};
+use reqwest::{
+    header::{
+        HeaderMap, ACCEPT, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        LINK, USER_AGENT,
+    },
+    StatusCode,
+};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tracing::debug;
 
 use crate::{
-    api::pagination::parse_next_link,
+    api::{
+        cache::{CachedResponse, HttpCache},
+        pagination::{parse_link_header, PageLinks},
+        rate_limit,
+        token_pool::TokenPool,
+    },
+    auth::Credential,
     config::RuntimeConfig,
+    crypto::EncryptionKey,
     error::{ApiError, Result},
 };
 
@@ -13,23 +29,50 @@ use crate::{
 pub struct GitHubClient {
     http: reqwest::Client,
     base_url: String,
-    token: Option<String>,
+    tokens: TokenPool,
+    cache: Option<HttpCache>,
+    page_concurrency: usize,
+    max_retries: u32,
 }
 
 impl GitHubClient {
-    pub fn from_runtime(runtime: &RuntimeConfig, token: Option<String>) -> Result<Self> {
+    pub fn from_runtime(runtime: &RuntimeConfig, credentials: Vec<Credential>) -> Result<Self> {
         let http = reqwest::Client::builder()
             .timeout(Duration::from_secs(runtime.request_timeout_seconds))
             .build()
             .map_err(ApiError::from)?;
 
+        let tokens = TokenPool::new(credentials, runtime.rate_limit_reserve);
+        let cache = match &runtime.http_cache_dir {
+            Some(dir) => {
+                let key = runtime
+                    .http_cache_key_file
+                    .as_deref()
+                    .map(EncryptionKey::from_file)
+                    .transpose()?;
+                Some(HttpCache::new(
+                    dir,
+                    tokens.identity().as_deref(),
+                    key.map(Arc::new),
+                ))
+            }
+            None => None,
+        };
+
         Ok(Self {
             http,
             base_url: runtime.api_base_url.clone(),
-            token,
+            tokens,
+            cache,
+            page_concurrency: runtime.page_concurrency,
+            max_retries: runtime.max_retries,
         })
     }
 
+    pub fn tokens(&self) -> &TokenPool {
+        &self.tokens
+    }
+
     pub async fn get_json<T: DeserializeOwned>(
         &self,
         path: &str,
@@ -42,26 +85,74 @@ impl GitHubClient {
         &self,
         path: &str,
     ) -> std::result::Result<Vec<T>, ApiError> {
-        let mut url = self.build_url(path);
-        let mut items = Vec::new();
+        self.paginate(path).try_collect().await
+    }
+
+    /// Streams the items of a paginated listing. When the first response
+    /// links to the last page, the remaining pages are requested up to
+    /// `page_concurrency` at a time; otherwise `next` links are followed one
+    /// page at a time. Items are yielded in page order either way.
+    pub fn paginate<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        path: &str,
+    ) -> impl Stream<Item = std::result::Result<T, ApiError>> + 'a {
+        let first_url = self.build_url(path);
 
-        loop {
-            let (page, headers) = self.get_json_from_url_with_headers::<Vec<T>>(&url).await?;
-            items.extend(page);
+        stream::once(async move { self.get_page::<T>(&first_url).await })
+            .map_ok(move |(items, links)| {
+                let rest = match links.remaining_pages() {
+                    Some(urls) if self.page_concurrency > 1 => {
+                        Either::Left(self.fetch_pages_concurrently(urls))
+                    }
+                    _ => Either::Right(self.follow_next_links(links.next)),
+                };
+                stream::iter(items.into_iter().map(Ok)).chain(rest)
+            })
+            .try_flatten()
+    }
 
-            let next_link = headers
-                .get(LINK)
-                .and_then(|value| value.to_str().ok())
-                .and_then(parse_next_link);
+    fn fetch_pages_concurrently<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        urls: Vec<String>,
+    ) -> impl Stream<Item = std::result::Result<T, ApiError>> + 'a {
+        debug!(pages = urls.len(), "fetching remaining pages concurrently");
 
-            if let Some(next_link) = next_link {
-                url = next_link;
-            } else {
-                break;
-            }
-        }
+        stream::iter(urls)
+            .map(move |url| async move {
+                let (items, _) = self.get_page::<T>(&url).await?;
+                Ok::<_, ApiError>(stream::iter(items.into_iter().map(Ok)))
+            })
+            .buffered(self.page_concurrency)
+            .try_flatten()
+    }
+
+    fn follow_next_links<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        next: Option<String>,
+    ) -> impl Stream<Item = std::result::Result<T, ApiError>> + 'a {
+        stream::try_unfold(next, move |url| async move {
+            let Some(url) = url else {
+                return Ok(None);
+            };
 
-        Ok(items)
+            let (items, links) = self.get_page::<T>(&url).await?;
+            Ok::<_, ApiError>(Some((stream::iter(items.into_iter().map(Ok)), links.next)))
+        })
+        .try_flatten()
+    }
+
+    async fn get_page<T: DeserializeOwned>(
+        &self,
+        url: &str,
+    ) -> std::result::Result<(Vec<T>, PageLinks), ApiError> {
+        let (items, headers) = self.get_json_from_url_with_headers::<Vec<T>>(url).await?;
+        let links = headers
+            .get(LINK)
+            .and_then(|value| value.to_str().ok())
+            .map(parse_link_header)
+            .unwrap_or_default();
+
+        Ok((items, links))
     }
 
     pub async fn get_json_with_headers<T: DeserializeOwned>(
@@ -84,21 +175,48 @@ impl GitHubClient {
         &self,
         url: &str,
     ) -> std::result::Result<(T, HeaderMap), ApiError> {
-        let mut request = self
-            .http
-            .get(url)
-            .header(
-                USER_AGENT,
-                format!("github-backup-rs/{}", env!("CARGO_PKG_VERSION")),
-            )
-            .header(ACCEPT, "application/vnd.github+json");
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(url));
+        let mut retries = 0;
+        let response = loop {
+            let mut request = self
+                .http
+                .get(url)
+                .header(
+                    USER_AGENT,
+                    format!("github-backup-rs/{}", env!("CARGO_PKG_VERSION")),
+                )
+                .header(ACCEPT, "application/vnd.github+json");
 
-        if let Some(token) = &self.token {
-            request = request.header(AUTHORIZATION, format!("token {token}"));
-        }
+            let slot = self.tokens.acquire(rate_limit::CORE).await;
+            if let Some(token) = slot.token() {
+                request = request.header(AUTHORIZATION, format!("token {token}"));
+            }
+
+            if let Some(cached) = &cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            let response = request.send().await?;
+            if let Some(response) = slot
+                .check_response(rate_limit::CORE, response, &mut retries, self.max_retries)
+                .await?
+            {
+                break response;
+            }
+        };
 
-        let response = request.send().await?;
         let status = response.status();
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (status, &cached) {
+            debug!(url, "http cache hit");
+            let value = serde_json::from_str::<T>(&cached.body)?;
+            return Ok((value, cached.headers()));
+        }
+
         if !status.is_success() {
             return Err(ApiError::UnexpectedStatus {
                 status,
@@ -110,7 +228,170 @@ impl GitHubClient {
         }
 
         let headers = response.headers().clone();
-        let value = response.json::<T>().await.map_err(ApiError::from)?;
+        let body = response.text().await?;
+        let value = serde_json::from_str::<T>(&body)?;
+
+        if let Some(cache) = &self.cache {
+            let header = |name| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string)
+            };
+            let entry = CachedResponse {
+                url: url.to_string(),
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+                link: header(LINK),
+                body,
+            };
+            if entry.etag.is_some() || entry.last_modified.is_some() {
+                if let Err(error) = cache.store(&entry) {
+                    debug!(url, error = %error, "failed writing http cache entry");
+                }
+            }
+        }
+
         Ok((value, headers))
     }
 }
+
+/// Returns the notice URL of an error body GitHub sends for repositories
+/// blocked for legal reasons, with status 451 or 403.
+pub(crate) fn extract_legal_url(message: &str) -> Option<String> {
+    let value = serde_json::from_str::<Value>(message).ok()?;
+    value
+        .get("block")
+        .and_then(|block| block.get("html_url"))
+        .and_then(Value::as_str)
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, Response, TestServer};
+
+    async fn etag_server() -> TestServer {
+        TestServer::start(|request| {
+            if request.header("if-none-match") == Some("\"v1\"") {
+                Response::new(304).header("etag", "\"v1\"")
+            } else {
+                Response::json(200, r#"[{"name":"app"}]"#)
+                    .header("etag", "\"v1\"")
+                    .header("link", r#"<https://api.github.com/page2>; rel="next""#)
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn replays_cached_body_on_not_modified() {
+        let server = etag_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().to_string_lossy().to_string();
+        let config = test_support::config(&["owner", "-o", &output, "--api-base-url", &server.url]);
+        let client = GitHubClient::from_runtime(&config.runtime, Vec::new()).unwrap();
+
+        let (first, _) = client
+            .get_json_with_headers::<Value>("/users/owner/repos")
+            .await
+            .unwrap();
+        let (second, headers) = client
+            .get_json_with_headers::<Value>("/users/owner/repos")
+            .await
+            .unwrap();
+
+        assert_eq!(first, json!([{ "name": "app" }]));
+        assert_eq!(second, first);
+        assert!(headers.contains_key(LINK));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("if-none-match"), None);
+        assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+    }
+
+    #[tokio::test]
+    async fn sends_unconditional_requests_without_cache() {
+        let server = etag_server().await;
+        let config =
+            test_support::config(&["owner", "--api-base-url", &server.url, "--no-http-cache"]);
+        let client = GitHubClient::from_runtime(&config.runtime, Vec::new()).unwrap();
+
+        for _ in 0..2 {
+            client
+                .get_json::<Value>("/users/owner/repos")
+                .await
+                .unwrap();
+        }
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.header("if-none-match").is_none()));
+    }
+
+    #[tokio::test]
+    async fn permission_denied_fails_without_retrying() {
+        // A budget below `--rate-limit-reserve` made any 403 wait for the
+        // reset an hour away.
+        let reset = (rate_limit::epoch_now() + 3_600).to_string();
+        let server = TestServer::start(move |_| {
+            Response::json(
+                403,
+                r#"{"message":"Resource not accessible by integration"}"#,
+            )
+            .header("x-ratelimit-limit", "5000")
+            .header("x-ratelimit-remaining", "50")
+            .header("x-ratelimit-reset", &reset)
+        })
+        .await;
+        let config =
+            test_support::config(&["owner", "--api-base-url", &server.url, "--no-http-cache"]);
+        let client = GitHubClient::from_runtime(&config.runtime, Vec::new()).unwrap();
+
+        let error = client
+            .get_json::<Value>("/repos/owner/private")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ApiError::UnexpectedStatus { status, message }
+                if *status == StatusCode::FORBIDDEN && message.contains("not accessible")),
+            "{error}"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_retried_up_to_max_retries() {
+        let server = TestServer::start(|_| {
+            Response::json(
+                403,
+                r#"{"message":"You have exceeded a secondary rate limit."}"#,
+            )
+            .header("retry-after", "1")
+        })
+        .await;
+        let config = test_support::config(&[
+            "owner",
+            "--api-base-url",
+            &server.url,
+            "--no-http-cache",
+            "--max-retries",
+            "1",
+        ]);
+        let client = GitHubClient::from_runtime(&config.runtime, Vec::new()).unwrap();
+
+        let error = client
+            .get_json::<Value>("/users/owner/repos")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ApiError::RetriesExhausted(message) if message.contains("attempts=2")),
+            "{error}"
+        );
+        assert_eq!(server.requests().len(), 2);
+    }
+}
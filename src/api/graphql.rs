@@ -0,0 +1,686 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+use crate::{
+    api::{
+        rate_limit,
+        token_pool::TokenPool,
+        types::{Comment, Issue, IssueTree, PullRequest, PullRequestTree, Repository},
+    },
+    auth::Credential,
+    config::RuntimeConfig,
+    error::{ApiError, AuthError, Result},
+};
+
+const PAGE_SIZE: u32 = 100;
+/// Pause when fewer points than this multiple of the last query cost remain.
+const COST_RESERVE_MULTIPLIER: u64 = 2;
+
+const RATE_LIMIT_FIELDS: &str = "rateLimit { cost remaining resetAt }";
+const REPOSITORY_FIELDS: &str =
+    "databaseId name nameWithOwner isArchived primaryLanguage { name } url sshUrl diskUsage";
+const COMMENT_FIELDS: &str = "databaseId author { login } body createdAt";
+
+/// Whose repositories to list. `Viewer` covers repositories the token owner
+/// owns, collaborates on or can access through organization membership;
+/// `User` the same for another user, as far as the token can see them, like
+/// the REST `/users/{user}/repos?type=all` listing.
+#[derive(Debug, Clone)]
+pub enum RepositoryOwner {
+    Viewer,
+    User(String),
+    Organization(String),
+}
+
+/// Client for the GitHub GraphQL API, which lists repositories and returns
+/// nested data such as issues with their labels and comments in far fewer
+/// requests than the REST API.
+#[derive(Clone)]
+pub struct GraphQlClient {
+    http: reqwest::Client,
+    endpoint: String,
+    tokens: TokenPool,
+    max_retries: u32,
+}
+
+impl GraphQlClient {
+    pub fn from_runtime(runtime: &RuntimeConfig, credentials: Vec<Credential>) -> Result<Self> {
+        Self::with_tokens(
+            runtime,
+            TokenPool::new(credentials, runtime.rate_limit_reserve),
+        )
+    }
+
+    /// Builds a client drawing on `tokens`, e.g. the pool of the REST client,
+    /// so both share the rate limit budgets and the run report counts every
+    /// request once.
+    pub fn with_tokens(runtime: &RuntimeConfig, tokens: TokenPool) -> Result<Self> {
+        if tokens.identity().is_none() {
+            return Err(AuthError::MissingToken.into());
+        }
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(runtime.request_timeout_seconds))
+            .build()
+            .map_err(ApiError::from)?;
+
+        Ok(Self {
+            http,
+            endpoint: graphql_endpoint(&runtime.api_base_url),
+            tokens,
+            max_retries: runtime.max_retries,
+        })
+    }
+
+    pub fn tokens(&self) -> &TokenPool {
+        &self.tokens
+    }
+
+    pub async fn viewer_login(&self) -> std::result::Result<String, ApiError> {
+        let data = self.query("query { viewer { login } }", json!({})).await?;
+        data.pointer("/viewer/login")
+            .and_then(Value::as_str)
+            .map(ToString::to_string)
+            .ok_or_else(|| ApiError::GraphQl("response is missing viewer.login".to_string()))
+    }
+
+    pub async fn list_repositories(
+        &self,
+        owner: &RepositoryOwner,
+    ) -> std::result::Result<Vec<Repository>, ApiError> {
+        let (query, variables, path) = match owner {
+            RepositoryOwner::Viewer => (
+                format!(
+                    "query($cursor: String) {{ {RATE_LIMIT_FIELDS} viewer {{ \
+                     repositories(first: {PAGE_SIZE}, after: $cursor, \
+                     affiliations: [OWNER, COLLABORATOR, ORGANIZATION_MEMBER], \
+                     orderBy: {{field: NAME, direction: ASC}}) {{ \
+                     pageInfo {{ hasNextPage endCursor }} nodes {{ {REPOSITORY_FIELDS} }} }} }} }}"
+                ),
+                json!({}),
+                "/viewer/repositories",
+            ),
+            RepositoryOwner::User(login) => (
+                format!(
+                    "query($login: String!, $cursor: String) {{ {RATE_LIMIT_FIELDS} \
+                     user(login: $login) {{ \
+                     repositories(first: {PAGE_SIZE}, after: $cursor, \
+                     ownerAffiliations: [OWNER, COLLABORATOR, ORGANIZATION_MEMBER], \
+                     orderBy: {{field: NAME, direction: ASC}}) {{ \
+                     pageInfo {{ hasNextPage endCursor }} nodes {{ {REPOSITORY_FIELDS} }} }} }} }}"
+                ),
+                json!({ "login": login }),
+                "/user/repositories",
+            ),
+            RepositoryOwner::Organization(login) => (
+                format!(
+                    "query($login: String!, $cursor: String) {{ {RATE_LIMIT_FIELDS} \
+                     organization(login: $login) {{ \
+                     repositories(first: {PAGE_SIZE}, after: $cursor, \
+                     orderBy: {{field: NAME, direction: ASC}}) {{ \
+                     pageInfo {{ hasNextPage endCursor }} nodes {{ {REPOSITORY_FIELDS} }} }} }} }}"
+                ),
+                json!({ "login": login }),
+                "/organization/repositories",
+            ),
+        };
+
+        let nodes = self
+            .paginate::<RepositoryNode>(&query, variables, path)
+            .await?;
+        Ok(nodes.into_iter().map(Into::into).collect())
+    }
+
+    /// Fetches every issue of a repository with its labels and comments.
+    pub async fn list_issue_trees(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> std::result::Result<Vec<IssueTree>, ApiError> {
+        let trees = self.list_trees(owner, name, "issues", "issue").await?;
+        Ok(trees
+            .into_iter()
+            .map(|(node, comments)| IssueTree {
+                issue: Issue {
+                    id: node.database_id,
+                    number: node.number,
+                    title: node.title,
+                    state: node.state.to_lowercase(),
+                },
+                labels: node.labels.into_names(),
+                comments,
+            })
+            .collect())
+    }
+
+    /// Fetches every pull request of a repository with its labels and
+    /// conversation comments.
+    pub async fn list_pull_request_trees(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> std::result::Result<Vec<PullRequestTree>, ApiError> {
+        let trees = self
+            .list_trees(owner, name, "pullRequests", "pullRequest")
+            .await?;
+        Ok(trees
+            .into_iter()
+            .map(|(node, comments)| PullRequestTree {
+                pull_request: PullRequest {
+                    id: node.database_id,
+                    number: node.number,
+                    title: node.title,
+                    state: node.state.to_lowercase(),
+                },
+                labels: node.labels.into_names(),
+                comments,
+            })
+            .collect())
+    }
+
+    /// Lists the issues or pull requests (`connection`) of a repository with
+    /// the first page of their comments in one query per page, then fetches
+    /// the remaining comments of those that have more through `field`.
+    async fn list_trees(
+        &self,
+        owner: &str,
+        name: &str,
+        connection: &str,
+        field: &str,
+    ) -> std::result::Result<Vec<(TreeNode, Vec<Comment>)>, ApiError> {
+        let query = format!(
+            "query($owner: String!, $name: String!, $cursor: String) {{ {RATE_LIMIT_FIELDS} \
+             repository(owner: $owner, name: $name) {{ \
+             {connection}(first: {PAGE_SIZE}, after: $cursor) {{ \
+             pageInfo {{ hasNextPage endCursor }} nodes {{ \
+             databaseId number title state labels(first: {PAGE_SIZE}) {{ nodes {{ name }} }} \
+             comments(first: {PAGE_SIZE}) {{ pageInfo {{ hasNextPage endCursor }} \
+             nodes {{ {COMMENT_FIELDS} }} }} }} }} }} }}"
+        );
+        let nodes = self
+            .paginate::<TreeNode>(
+                &query,
+                json!({ "owner": owner, "name": name }),
+                &format!("/repository/{connection}"),
+            )
+            .await?;
+
+        let mut trees = Vec::with_capacity(nodes.len());
+        for mut node in nodes {
+            let comments = self
+                .complete_comments(owner, name, field, &mut node)
+                .await?;
+            trees.push((node, comments));
+        }
+        Ok(trees)
+    }
+
+    async fn complete_comments(
+        &self,
+        owner: &str,
+        name: &str,
+        field: &str,
+        node: &mut TreeNode,
+    ) -> std::result::Result<Vec<Comment>, ApiError> {
+        let page_info = node.comments.page_info.clone();
+        let mut comments = std::mem::take(&mut node.comments.nodes)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<Comment>>();
+
+        let (true, Some(cursor)) = (page_info.has_next_page, page_info.end_cursor) else {
+            return Ok(comments);
+        };
+
+        let query = format!(
+            "query($owner: String!, $name: String!, $number: Int!, $cursor: String) {{ \
+             {RATE_LIMIT_FIELDS} repository(owner: $owner, name: $name) {{ \
+             {field}(number: $number) {{ comments(first: {PAGE_SIZE}, after: $cursor) {{ \
+             pageInfo {{ hasNextPage endCursor }} nodes {{ {COMMENT_FIELDS} }} }} }} }} }}"
+        );
+        let remaining = self
+            .paginate::<CommentNode>(
+                &query,
+                json!({ "owner": owner, "name": name, "number": node.number, "cursor": cursor }),
+                &format!("/repository/{field}/comments"),
+            )
+            .await?;
+        comments.extend(remaining.into_iter().map(Into::into));
+
+        Ok(comments)
+    }
+
+    /// Follows a connection's cursor until every node has been fetched. The
+    /// query must take a `$cursor: String` variable and select `pageInfo`
+    /// and `nodes` on the connection found at `path`.
+    pub async fn paginate<N: DeserializeOwned>(
+        &self,
+        query: &str,
+        mut variables: Value,
+        path: &str,
+    ) -> std::result::Result<Vec<N>, ApiError> {
+        let mut items = Vec::new();
+
+        loop {
+            let data = self.query(query, variables.clone()).await?;
+            let connection = data
+                .pointer(path)
+                .cloned()
+                .ok_or_else(|| ApiError::GraphQl(format!("response is missing {path}")))?;
+            let page = serde_json::from_value::<Connection<N>>(connection)?;
+            items.extend(page.nodes);
+
+            match page.page_info.end_cursor {
+                Some(cursor) if page.page_info.has_next_page => {
+                    variables["cursor"] = Value::String(cursor);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Runs a query and returns its `data` object. When the query selects
+    /// `rateLimit`, waits for the reset once the remaining points run low.
+    pub async fn query(
+        &self,
+        query: &str,
+        variables: Value,
+    ) -> std::result::Result<Value, ApiError> {
+        let mut retries = 0;
+        let response = loop {
+            let slot = self.tokens.acquire(rate_limit::GRAPHQL).await;
+            let response = self
+                .http
+                .post(&self.endpoint)
+                .header(
+                    USER_AGENT,
+                    format!("github-backup-rs/{}", env!("CARGO_PKG_VERSION")),
+                )
+                .header(ACCEPT, "application/json")
+                .header(
+                    AUTHORIZATION,
+                    format!("bearer {}", slot.token().unwrap_or_default()),
+                )
+                .json(&json!({ "query": query, "variables": variables }))
+                .send()
+                .await?;
+
+            if let Some(response) = slot
+                .check_response(
+                    rate_limit::GRAPHQL,
+                    response,
+                    &mut retries,
+                    self.max_retries,
+                )
+                .await?
+            {
+                break response;
+            }
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ApiError::UnexpectedStatus {
+                status,
+                message: response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "<failed to read error body>".to_string()),
+            });
+        }
+
+        let body = response.json::<GraphQlResponse>().await?;
+        if let Some(errors) = body.errors.filter(|errors| !errors.is_empty()) {
+            let messages = errors
+                .into_iter()
+                .map(|error| error.message)
+                .collect::<Vec<_>>();
+            return Err(ApiError::GraphQl(messages.join("; ")));
+        }
+
+        let data = body.data.unwrap_or(Value::Null);
+        if let Some(rate_limit) = data
+            .get("rateLimit")
+            .cloned()
+            .and_then(|value| serde_json::from_value::<RateLimit>(value).ok())
+        {
+            self.respect_cost(&rate_limit).await;
+        }
+
+        Ok(data)
+    }
+
+    async fn respect_cost(&self, rate_limit: &RateLimit) {
+        debug!(
+            cost = rate_limit.cost,
+            remaining = rate_limit.remaining,
+            "graphql query cost"
+        );
+
+        // With several tokens the pool pauses the exhausted one instead.
+        if self.tokens.token_count() > 1
+            || rate_limit.remaining >= rate_limit.cost.max(1) * COST_RESERVE_MULTIPLIER
+        {
+            return;
+        }
+
+        let wait = (rate_limit.reset_at - Utc::now())
+            .to_std()
+            .unwrap_or_default();
+        info!(
+            remaining = rate_limit.remaining,
+            wait_seconds = wait.as_secs(),
+            "graphql rate limit nearly exhausted, waiting for reset",
+        );
+        sleep(wait).await;
+    }
+}
+
+/// Derives the GraphQL endpoint from the REST base URL, e.g.
+/// `https://ghe.example.com/api/v3` becomes `https://ghe.example.com/api/graphql`.
+fn graphql_endpoint(api_base_url: &str) -> String {
+    let base = api_base_url.trim_end_matches('/');
+    match base.strip_suffix("/v3") {
+        Some(prefix) => format!("{prefix}/graphql"),
+        None => format!("{base}/graphql"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlResponse {
+    data: Option<Value>,
+    errors: Option<Vec<GraphQlError>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimit {
+    cost: u64,
+    remaining: u64,
+    reset_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection<N> {
+    page_info: PageInfo,
+    nodes: Vec<N>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RepositoryNode {
+    database_id: u64,
+    name: String,
+    name_with_owner: String,
+    is_archived: bool,
+    primary_language: Option<NamedNode>,
+    url: String,
+    ssh_url: String,
+    /// Kilobytes, absent when the token cannot see it.
+    disk_usage: Option<u64>,
+}
+
+impl From<RepositoryNode> for Repository {
+    fn from(node: RepositoryNode) -> Self {
+        Self {
+            id: node.database_id,
+            name: node.name,
+            full_name: node.name_with_owner,
+            archived: node.is_archived,
+            language: node.primary_language.map(|language| language.name),
+            clone_url: format!("{}.git", node.url),
+            ssh_url: node.ssh_url,
+            size: node.disk_usage.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NamedNode {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Labels {
+    nodes: Vec<NamedNode>,
+}
+
+impl Labels {
+    fn into_names(self) -> Vec<String> {
+        self.nodes.into_iter().map(|label| label.name).collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TreeNode {
+    database_id: u64,
+    number: u64,
+    title: String,
+    state: String,
+    labels: Labels,
+    comments: Connection<CommentNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CommentNode {
+    database_id: u64,
+    author: Option<Author>,
+    body: String,
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    login: String,
+}
+
+impl From<CommentNode> for Comment {
+    fn from(node: CommentNode) -> Self {
+        Self {
+            id: node.database_id,
+            author: node.author.map(|author| author.login),
+            body: node.body,
+            created_at: node.created_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Response, TestServer};
+
+    #[test]
+    fn derives_endpoint_from_rest_base_url() {
+        assert_eq!(
+            graphql_endpoint("https://api.github.com"),
+            "https://api.github.com/graphql"
+        );
+        assert_eq!(
+            graphql_endpoint("https://ghe.example.com/api/v3/"),
+            "https://ghe.example.com/api/graphql"
+        );
+        assert_eq!(
+            graphql_endpoint("https://ghe.example.com/api/v3"),
+            "https://ghe.example.com/api/graphql"
+        );
+    }
+
+    fn repository(id: u64, name: &str) -> Value {
+        json!({
+            "databaseId": id,
+            "name": name,
+            "nameWithOwner": format!("acme/{name}"),
+            "isArchived": false,
+            "primaryLanguage": null,
+            "url": format!("https://github.com/acme/{name}"),
+            "sshUrl": format!("git@github.com:acme/{name}.git"),
+            "diskUsage": 42,
+        })
+    }
+
+    fn client(server: &TestServer) -> GraphQlClient {
+        let config = test_support::config(&["acme", "--api-base-url", &server.url]);
+        let credentials = vec![Credential {
+            source: "test".to_string(),
+            token: "token".to_string(),
+        }];
+        GraphQlClient::from_runtime(&config.runtime, credentials).unwrap()
+    }
+
+    #[tokio::test]
+    async fn follows_repository_cursors() {
+        let server = TestServer::start(|request| {
+            let body = serde_json::from_slice::<Value>(&request.body).unwrap();
+            let (nodes, page_info) = match body.pointer("/variables/cursor") {
+                None | Some(Value::Null) => (
+                    vec![repository(1, "api"), repository(2, "web")],
+                    json!({ "hasNextPage": true, "endCursor": "page2" }),
+                ),
+                Some(cursor) if cursor == "page2" => (
+                    vec![repository(3, "docs")],
+                    json!({ "hasNextPage": false, "endCursor": "page3" }),
+                ),
+                Some(cursor) => panic!("unexpected cursor {cursor}"),
+            };
+            let data = json!({ "data": {
+                "organization": { "repositories": { "pageInfo": page_info, "nodes": nodes } },
+            }});
+            Response::json(200, &data.to_string())
+        })
+        .await;
+
+        let repositories = client(&server)
+            .list_repositories(&RepositoryOwner::Organization("acme".to_string()))
+            .await
+            .unwrap();
+
+        let names = repositories
+            .iter()
+            .map(|repository| repository.full_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["acme/api", "acme/web", "acme/docs"]);
+        assert_eq!(repositories[0].clone_url, "https://github.com/acme/api.git");
+        assert_eq!(repositories[0].size, 42);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.path == "/graphql"
+            && request.header("authorization") == Some("bearer token")));
+        let first = serde_json::from_slice::<Value>(&requests[0].body).unwrap();
+        assert_eq!(first["variables"]["login"], "acme");
+    }
+
+    #[tokio::test]
+    async fn reports_query_errors() {
+        let server = TestServer::start(|_| {
+            Response::json(
+                200,
+                r#"{"data":null,"errors":[{"message":"Could not resolve to an Organization"}]}"#,
+            )
+        })
+        .await;
+
+        let error = client(&server)
+            .list_repositories(&RepositoryOwner::Organization("missing".to_string()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ApiError::GraphQl(message) if message.contains("Could not resolve")),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn requires_a_token() {
+        let config = test_support::config(&["acme"]);
+        assert!(GraphQlClient::from_runtime(&config.runtime, Vec::new()).is_err());
+    }
+
+    fn comment(id: u64, body: &str) -> Value {
+        json!({
+            "databaseId": id,
+            "author": { "login": "octocat" },
+            "body": body,
+            "createdAt": "2026-01-01T00:00:00Z",
+        })
+    }
+
+    fn page(nodes: Vec<Value>, next: Option<&str>) -> Value {
+        json!({
+            "pageInfo": { "hasNextPage": next.is_some(), "endCursor": next },
+            "nodes": nodes,
+        })
+    }
+
+    #[tokio::test]
+    async fn fetches_issue_trees_with_every_comment() {
+        let server = TestServer::start(|request| {
+            let body = serde_json::from_slice::<Value>(&request.body).unwrap();
+            let query = body["query"].as_str().unwrap();
+            let data = if query.contains("issue(number: $number)") {
+                assert_eq!(body["variables"]["number"], 7);
+                assert_eq!(body["variables"]["cursor"], "comments-1");
+                json!({ "repository": { "issue": {
+                    "comments": page(vec![comment(3, "third")], None),
+                }}})
+            } else {
+                assert_eq!(body["variables"]["owner"], "acme");
+                assert_eq!(body["variables"]["name"], "api");
+                json!({ "repository": { "issues": page(vec![json!({
+                    "databaseId": 70,
+                    "number": 7,
+                    "title": "Broken build",
+                    "state": "OPEN",
+                    "labels": { "nodes": [{ "name": "bug" }] },
+                    "comments": page(
+                        vec![comment(1, "first"), comment(2, "second")],
+                        Some("comments-1"),
+                    ),
+                })], None) }})
+            };
+            Response::json(200, &json!({ "data": data }).to_string())
+        })
+        .await;
+
+        let trees = client(&server)
+            .list_issue_trees("acme", "api")
+            .await
+            .unwrap();
+
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].issue.number, 7);
+        assert_eq!(trees[0].issue.state, "open");
+        assert_eq!(trees[0].labels, ["bug"]);
+        let bodies = trees[0]
+            .comments
+            .iter()
+            .map(|comment| comment.body.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, ["first", "second", "third"]);
+        assert_eq!(trees[0].comments[0].author.as_deref(), Some("octocat"));
+        assert_eq!(server.requests().len(), 2);
+    }
+}
@@ -1,5 +1,8 @@
+pub mod cache;
 pub mod client;
+pub mod graphql;
 pub mod pagination;
 pub mod rate_limit;
 pub mod retry;
+pub mod token_pool;
 pub mod types;
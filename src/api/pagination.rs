@@ -1,21 +1,101 @@
-pub fn parse_next_link(link_header: &str) -> Option<String> {
+use reqwest::Url;
+
+/// Targets of the `Link` response header used by paginated endpoints.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageLinks {
+    pub first: Option<String>,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    pub last: Option<String>,
+}
+
+impl PageLinks {
+    /// URLs of every page from `next` up to `last`, when both use numbered
+    /// `page` parameters. Cursor-paginated endpoints return `None` and have to
+    /// be followed one page at a time.
+    pub fn remaining_pages(&self) -> Option<Vec<String>> {
+        let next = self.next.as_deref()?;
+        let next_page = page_number(next)?;
+        let last_page = page_number(self.last.as_deref()?)?;
+        if last_page < next_page {
+            return None;
+        }
+
+        (next_page..=last_page)
+            .map(|page| with_page_number(next, page))
+            .collect()
+    }
+}
+
+pub fn parse_link_header(link_header: &str) -> PageLinks {
+    let mut links = PageLinks::default();
+
     for entry in link_header.split(',') {
         let section = entry.trim();
-        if !section.contains("rel=\"next\"") {
+        let Some(url) = section
+            .strip_prefix('<')
+            .and_then(|rest| rest.split_once('>'))
+            .map(|(url, _)| url.to_string())
+        else {
             continue;
-        }
+        };
 
-        let start = section.find('<')? + 1;
-        let end = section[start..].find('>')? + start;
-        return Some(section[start..end].to_string());
+        for parameter in section.split(';').skip(1) {
+            let Some(relations) = parameter.trim().strip_prefix("rel=") else {
+                continue;
+            };
+
+            for relation in relations.trim_matches('"').split_whitespace() {
+                let slot = match relation {
+                    "first" => &mut links.first,
+                    "prev" => &mut links.prev,
+                    "next" => &mut links.next,
+                    "last" => &mut links.last,
+                    _ => continue,
+                };
+                *slot = Some(url.clone());
+            }
+        }
     }
 
-    None
+    links
+}
+
+pub fn parse_next_link(link_header: &str) -> Option<String> {
+    parse_link_header(link_header).next
+}
+
+fn page_number(url: &str) -> Option<u32> {
+    Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(name, _)| name == "page")?
+        .1
+        .parse()
+        .ok()
+}
+
+fn with_page_number(url: &str, page: u32) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    let pairs = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if name == "page" {
+                page.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect::<Vec<_>>();
+
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    Some(url.to_string())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse_next_link;
+    use super::{parse_link_header, parse_next_link};
 
     #[test]
     fn extracts_next_link() {
@@ -25,4 +105,47 @@ mod tests {
             Some("https://api.github.com/resource?page=2")
         );
     }
+
+    #[test]
+    fn extracts_all_relations() {
+        let link = "<https://api.github.com/resource?page=2>; rel=\"prev\", <https://api.github.com/resource?page=4>; rel=\"next\", <https://api.github.com/resource?page=9>; rel=\"last\", <https://api.github.com/resource?page=1>; rel=\"first\"";
+        let links = parse_link_header(link);
+
+        assert_eq!(
+            links.first.as_deref(),
+            Some("https://api.github.com/resource?page=1")
+        );
+        assert_eq!(
+            links.prev.as_deref(),
+            Some("https://api.github.com/resource?page=2")
+        );
+        assert_eq!(
+            links.next.as_deref(),
+            Some("https://api.github.com/resource?page=4")
+        );
+        assert_eq!(
+            links.last.as_deref(),
+            Some("https://api.github.com/resource?page=9")
+        );
+    }
+
+    #[test]
+    fn expands_remaining_pages_from_last_link() {
+        let link = "<https://api.github.com/orgs/acme/repos?per_page=100&page=2>; rel=\"next\", <https://api.github.com/orgs/acme/repos?per_page=100&page=4>; rel=\"last\"";
+
+        assert_eq!(
+            parse_link_header(link).remaining_pages(),
+            Some(vec![
+                "https://api.github.com/orgs/acme/repos?per_page=100&page=2".to_string(),
+                "https://api.github.com/orgs/acme/repos?per_page=100&page=3".to_string(),
+                "https://api.github.com/orgs/acme/repos?per_page=100&page=4".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn cursor_links_are_not_expanded() {
+        let link = "<https://api.github.com/resource?after=abc>; rel=\"next\"";
+        assert_eq!(parse_link_header(link).remaining_pages(), None);
+    }
 }
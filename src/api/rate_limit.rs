@@ -1,4 +1,15 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use serde::Serialize;
+use tracing::debug;
 
 pub fn calculate_retry_delay(
     attempt: u32,
@@ -29,3 +40,343 @@ pub fn calculate_retry_delay(
 
     Duration::from_millis(base.saturating_mul(1000).saturating_add(jitter))
 }
+
+/// Rate limit state reported by the most recent response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateLimitSnapshot {
+    pub resource: String,
+    pub limit: u64,
+    pub remaining: u64,
+    pub used: u64,
+    pub reset_epoch: u64,
+}
+
+impl RateLimitSnapshot {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let number = |name: &str| header(name).and_then(|value| value.parse::<u64>().ok());
+
+        let limit = number("x-ratelimit-limit")?;
+        let remaining = number("x-ratelimit-remaining")?;
+        Some(Self {
+            resource: header("x-ratelimit-resource").unwrap_or("core").to_string(),
+            limit,
+            remaining,
+            used: number("x-ratelimit-used").unwrap_or(limit.saturating_sub(remaining)),
+            reset_epoch: number("x-ratelimit-reset").unwrap_or_default(),
+        })
+    }
+}
+
+/// Resource of the REST API, also assumed when a response names none.
+pub const CORE: &str = "core";
+/// Resource of the GraphQL API, which GitHub meters separately from REST.
+pub const GRAPHQL: &str = "graphql";
+
+/// Budget of one token, shared by every request made with it. GitHub meters
+/// each `x-ratelimit-resource` separately, so one snapshot is kept per
+/// resource. Once fewer than `reserve` requests of a resource remain the
+/// token is held back for it until the reset, leaving that headroom to other
+/// tools using the same token. The reserve is capped at a tenth of the limit
+/// so low unauthenticated limits are not reserved entirely. A secondary rate
+/// limit's `Retry-After` pauses the token for every resource.
+#[derive(Debug, Clone)]
+pub struct RateLimitBudget {
+    reserve: u64,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    resources: BTreeMap<String, RateLimitSnapshot>,
+    paused_until: Option<u64>,
+}
+
+impl RateLimitBudget {
+    pub fn new(reserve: u64) -> Self {
+        Self {
+            reserve,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, headers: &HeaderMap) {
+        let Some(snapshot) = RateLimitSnapshot::from_headers(headers) else {
+            return;
+        };
+
+        debug!(
+            resource = %snapshot.resource,
+            remaining = snapshot.remaining,
+            limit = snapshot.limit,
+            "rate limit budget updated"
+        );
+        self.lock()
+            .resources
+            .insert(snapshot.resource.clone(), snapshot);
+    }
+
+    /// Pauses the token for every resource until `until`, as asked by the
+    /// `Retry-After` of a secondary rate limit.
+    pub fn pause_until(&self, until: u64) {
+        let mut state = self.lock();
+        state.paused_until = state.paused_until.max(Some(until));
+    }
+
+    /// Latest snapshot of every resource seen so far, ordered by resource.
+    pub fn snapshots(&self) -> Vec<RateLimitSnapshot> {
+        self.lock().resources.values().cloned().collect()
+    }
+
+    /// Requests of `resource` that can still be made before reaching the
+    /// reserve. Unknown budgets and budgets whose reset has passed count as
+    /// unlimited.
+    pub fn headroom(&self, resource: &str, now_epoch: u64) -> u64 {
+        let state = self.lock();
+        if state.paused_until.is_some_and(|until| until > now_epoch) {
+            return 0;
+        }
+
+        state.resources.get(resource).map_or(u64::MAX, |snapshot| {
+            headroom(snapshot, self.reserve, now_epoch)
+        })
+    }
+
+    /// Time until `resource` can be used again when the token is paused or
+    /// its budget is below the reserve.
+    pub fn resume_delay(&self, resource: &str, now_epoch: u64) -> Option<Duration> {
+        let state = self.lock();
+        let paused = state
+            .paused_until
+            .filter(|until| *until > now_epoch)
+            .map(|until| Duration::from_secs(until - now_epoch));
+        let throttled = state
+            .resources
+            .get(resource)
+            .and_then(|snapshot| throttle_delay(snapshot, self.reserve, now_epoch));
+        paused.max(throttled)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BudgetState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Pause after a secondary rate limit without `Retry-After`; GitHub asks to
+/// wait at least a minute.
+pub const SECONDARY_RATE_LIMIT_PAUSE_SECONDS: u64 = 60;
+
+/// Whether a rejected response is a rate limit rather than, for a 403, a
+/// missing permission: a 429, or a 403 with no requests remaining, a
+/// `Retry-After` or a secondary rate limit message.
+pub fn is_rate_limited(status: StatusCode, headers: &HeaderMap, message: &str) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::FORBIDDEN => {
+            headers
+                .get("x-ratelimit-remaining")
+                .is_some_and(|remaining| remaining.as_bytes() == b"0")
+                || headers.contains_key(RETRY_AFTER)
+                || is_secondary_rate_limit(message)
+        }
+        _ => false,
+    }
+}
+
+pub fn is_secondary_rate_limit(message: &str) -> bool {
+    message
+        .to_ascii_lowercase()
+        .contains("secondary rate limit")
+}
+
+/// Seconds to wait given by a `Retry-After` header. GitHub sends the
+/// delay-seconds form.
+pub fn retry_after(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn effective_reserve(snapshot: &RateLimitSnapshot, reserve: u64) -> u64 {
+    reserve.min(snapshot.limit / 10)
+}
+
+fn headroom(snapshot: &RateLimitSnapshot, reserve: u64, now_epoch: u64) -> u64 {
+    if snapshot.reset_epoch <= now_epoch {
+        return u64::MAX;
+    }
+
+    snapshot
+        .remaining
+        .saturating_sub(effective_reserve(snapshot, reserve))
+}
+
+fn throttle_delay(snapshot: &RateLimitSnapshot, reserve: u64, now_epoch: u64) -> Option<Duration> {
+    if headroom(snapshot, reserve, now_epoch) > 0 {
+        return None;
+    }
+
+    Some(Duration::from_secs(snapshot.reset_epoch - now_epoch + 1))
+}
+
+pub fn epoch_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{
+        header::{HeaderMap, HeaderValue},
+        StatusCode,
+    };
+
+    use super::{
+        headroom, is_rate_limited, retry_after, throttle_delay, RateLimitBudget, RateLimitSnapshot,
+        CORE, GRAPHQL,
+    };
+
+    fn snapshot(limit: u64, remaining: u64) -> RateLimitSnapshot {
+        RateLimitSnapshot {
+            resource: "core".to_string(),
+            limit,
+            remaining,
+            used: limit - remaining,
+            reset_epoch: 1_000,
+        }
+    }
+
+    #[test]
+    fn waits_for_reset_below_reserve() {
+        assert_eq!(
+            throttle_delay(&snapshot(5_000, 80), 100, 940),
+            Some(Duration::from_secs(61))
+        );
+        assert_eq!(throttle_delay(&snapshot(5_000, 200), 100, 940), None);
+        assert_eq!(throttle_delay(&snapshot(5_000, 80), 100, 1_000), None);
+    }
+
+    #[test]
+    fn caps_reserve_for_small_limits() {
+        assert_eq!(throttle_delay(&snapshot(60, 10), 100, 940), None);
+        assert!(throttle_delay(&snapshot(60, 6), 100, 940).is_some());
+    }
+
+    #[test]
+    fn headroom_resets_after_reset_time() {
+        assert_eq!(headroom(&snapshot(5_000, 350), 100, 940), 250);
+        assert_eq!(headroom(&snapshot(5_000, 50), 100, 940), 0);
+        assert_eq!(headroom(&snapshot(5_000, 50), 100, 1_000), u64::MAX);
+    }
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn limit_headers(resource: &str, remaining: u64, reset: u64) -> HeaderMap {
+        headers(&[
+            ("x-ratelimit-resource", resource.to_string()),
+            ("x-ratelimit-limit", "5000".to_string()),
+            ("x-ratelimit-remaining", remaining.to_string()),
+            ("x-ratelimit-reset", reset.to_string()),
+        ])
+    }
+
+    #[test]
+    fn forbidden_is_a_rate_limit_only_with_rate_limit_signs() {
+        let forbidden = |headers: &HeaderMap, message: &str| {
+            is_rate_limited(StatusCode::FORBIDDEN, headers, message)
+        };
+
+        assert!(forbidden(
+            &limit_headers(CORE, 0, 1_000),
+            "API rate limit exceeded"
+        ));
+        assert!(forbidden(
+            &headers(&[("retry-after", "60".to_string())]),
+            "{}"
+        ));
+        assert!(forbidden(
+            &HeaderMap::new(),
+            r#"{"message":"You have exceeded a secondary rate limit."}"#
+        ));
+        assert!(!forbidden(
+            &limit_headers(CORE, 50, 1_000),
+            r#"{"message":"Resource not accessible by integration"}"#
+        ));
+        assert!(is_rate_limited(
+            StatusCode::TOO_MANY_REQUESTS,
+            &HeaderMap::new(),
+            ""
+        ));
+        assert!(!is_rate_limited(
+            StatusCode::NOT_FOUND,
+            &limit_headers(CORE, 0, 1_000),
+            ""
+        ));
+    }
+
+    #[test]
+    fn keeps_one_budget_per_resource() {
+        let budget = RateLimitBudget::new(100);
+        budget.record(&limit_headers(GRAPHQL, 50, 1_000));
+        budget.record(&limit_headers(CORE, 4_000, 1_000));
+
+        assert_eq!(budget.headroom(CORE, 940), 3_900);
+        assert_eq!(budget.headroom(GRAPHQL, 940), 0);
+        assert_eq!(budget.resume_delay(CORE, 940), None);
+        assert_eq!(
+            budget.resume_delay(GRAPHQL, 940),
+            Some(Duration::from_secs(61))
+        );
+        assert_eq!(
+            budget
+                .snapshots()
+                .iter()
+                .map(|snapshot| snapshot.resource.as_str())
+                .collect::<Vec<_>>(),
+            [CORE, GRAPHQL]
+        );
+    }
+
+    #[test]
+    fn pause_holds_back_every_resource() {
+        let budget = RateLimitBudget::new(100);
+        budget.record(&limit_headers(CORE, 4_000, 1_000));
+        budget.pause_until(960);
+
+        assert_eq!(budget.headroom(CORE, 940), 0);
+        assert_eq!(budget.headroom(GRAPHQL, 940), 0);
+        assert_eq!(
+            budget.resume_delay(GRAPHQL, 940),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(budget.headroom(CORE, 960), 3_900);
+    }
+
+    #[test]
+    fn reads_retry_after_seconds() {
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "30".to_string())])),
+            Some(30)
+        );
+        assert_eq!(
+            retry_after(&headers(&[(
+                "retry-after",
+                "Wed, 21 Oct 2015 07:28:00 GMT".to_string()
+            )])),
+            None
+        );
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}
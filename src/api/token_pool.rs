@@ -0,0 +1,318 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use reqwest::{header::HeaderMap, Response, StatusCode};
+use serde::Serialize;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::{
+    api::rate_limit::{
+        calculate_retry_delay, epoch_now, is_rate_limited, is_secondary_rate_limit, retry_after,
+        RateLimitBudget, RateLimitSnapshot, SECONDARY_RATE_LIMIT_PAUSE_SECONDS,
+    },
+    auth::Credential,
+    error::ApiError,
+};
+
+/// Tokens used in rotation. Every request goes out with the token that has
+/// the most rate limit budget left for its resource; a token whose budget is
+/// exhausted or below the reserve is paused until its reset. Without
+/// credentials the pool holds a single anonymous slot.
+#[derive(Debug, Clone)]
+pub struct TokenPool {
+    slots: Arc<Vec<TokenSlot>>,
+}
+
+#[derive(Debug)]
+pub struct TokenSlot {
+    source: String,
+    token: Option<String>,
+    budget: RateLimitBudget,
+    requests: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+/// Per-token usage for the run report.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenStats {
+    pub source: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rate_limits: Vec<RateLimitSnapshot>,
+}
+
+impl TokenPool {
+    pub fn new(credentials: Vec<Credential>, reserve: u64) -> Self {
+        let mut slots = credentials
+            .into_iter()
+            .map(|credential| TokenSlot::new(credential.source, Some(credential.token), reserve))
+            .collect::<Vec<_>>();
+        if slots.is_empty() {
+            slots.push(TokenSlot::new("anonymous".to_string(), None, reserve));
+        }
+
+        Self {
+            slots: Arc::new(slots),
+        }
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Key identifying the set of tokens, e.g. to separate cached responses.
+    pub fn identity(&self) -> Option<String> {
+        let tokens = self
+            .slots
+            .iter()
+            .filter_map(|slot| slot.token.as_deref())
+            .collect::<Vec<_>>();
+        (!tokens.is_empty()).then(|| tokens.join("\n"))
+    }
+
+    /// Picks the token with the most budget left for `resource`, waiting for
+    /// the earliest reset when every token is paused.
+    pub async fn acquire(&self, resource: &str) -> &TokenSlot {
+        loop {
+            let now = epoch_now();
+            let best = self
+                .slots
+                .iter()
+                .max_by_key(|slot| slot.budget.headroom(resource, now))
+                .expect("token pool always has a slot");
+            if best.budget.headroom(resource, now) > 0 {
+                best.requests.fetch_add(1, Ordering::Relaxed);
+                return best;
+            }
+
+            let delay = self
+                .slots
+                .iter()
+                .filter_map(|slot| slot.budget.resume_delay(resource, now))
+                .min()
+                .unwrap_or(Duration::from_secs(1));
+            warn!(
+                resource,
+                tokens = self.slots.len(),
+                wait_seconds = delay.as_secs(),
+                "rate limit budget below reserve for every token, waiting for reset",
+            );
+            sleep(delay).await;
+        }
+    }
+
+    pub fn stats(&self) -> Vec<TokenStats> {
+        self.slots
+            .iter()
+            .map(|slot| TokenStats {
+                source: slot.source.clone(),
+                requests: slot.requests.load(Ordering::Relaxed),
+                rate_limited: slot.rate_limited.load(Ordering::Relaxed),
+                rate_limits: slot.budget.snapshots(),
+            })
+            .collect()
+    }
+}
+
+impl TokenSlot {
+    fn new(source: String, token: Option<String>, reserve: u64) -> Self {
+        Self {
+            source,
+            token,
+            budget: RateLimitBudget::new(reserve),
+            requests: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+        }
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn record(&self, headers: &HeaderMap) {
+        self.budget.record(headers);
+    }
+
+    /// Records the rate limit of a response to a `resource` request and
+    /// passes it on, or returns `None` when the rate limit rejected it and
+    /// the request should be sent again. `retries` counts the requests sent
+    /// again so far; once it reaches `max_retries` the rejection is returned
+    /// as an error without pausing the token. Other rejections, such as a 403 for a missing permission,
+    /// are returned as errors right away.
+    pub async fn check_response(
+        &self,
+        resource: &str,
+        response: Response,
+        retries: &mut u32,
+        max_retries: u32,
+    ) -> std::result::Result<Option<Response>, ApiError> {
+        let status = response.status();
+        if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+            self.record(response.headers());
+            return Ok(Some(response));
+        }
+
+        let headers = response.headers().clone();
+        let message = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "<failed to read error body>".to_string());
+        if !is_rate_limited(status, &headers, &message) {
+            self.record(&headers);
+            return Err(ApiError::UnexpectedStatus { status, message });
+        }
+        if *retries >= max_retries {
+            // Nothing is sent again, so the token is not paused for it.
+            self.record(&headers);
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(ApiError::RetriesExhausted(format!(
+                "rate limited with status {status}: {message}; attempts={}",
+                *retries + 1
+            )));
+        }
+        if !self.record_rate_limited(resource, &headers, &message) {
+            return Err(ApiError::UnexpectedStatus { status, message });
+        }
+
+        *retries += 1;
+        Ok(None)
+    }
+
+    /// Records a response to a `resource` request rejected by the rate limit.
+    /// A secondary rate limit pauses the token for its `Retry-After`, or a
+    /// minute when `message` names one without it. Returns whether the
+    /// request should be retried, which is the case when the token is now
+    /// paused until a known time and another attempt may use a different
+    /// token or wait for it.
+    pub fn record_rate_limited(&self, resource: &str, headers: &HeaderMap, message: &str) -> bool {
+        self.budget.record(headers);
+        let now = epoch_now();
+        let pause = retry_after(headers).or_else(|| {
+            is_secondary_rate_limit(message).then_some(SECONDARY_RATE_LIMIT_PAUSE_SECONDS)
+        });
+        if let Some(seconds) = pause {
+            let delay = calculate_retry_delay(0, Some(seconds), None);
+            self.budget.pause_until(now + delay.as_secs());
+        }
+        let Some(delay) = self.budget.resume_delay(resource, now) else {
+            return false;
+        };
+
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+        info!(
+            token = %self.source,
+            resource,
+            wait_seconds = delay.as_secs(),
+            "token hit its rate limit, pausing it",
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use super::*;
+    use crate::api::rate_limit::{CORE, GRAPHQL};
+
+    fn pool(sources: &[&str]) -> TokenPool {
+        let credentials = sources
+            .iter()
+            .map(|source| Credential {
+                source: source.to_string(),
+                token: format!("token-{source}"),
+            })
+            .collect();
+        TokenPool::new(credentials, 100)
+    }
+
+    fn headers(
+        resource: &str,
+        remaining: u64,
+        extra: &[(&'static str, &'static str)],
+    ) -> HeaderMap {
+        with_reset(resource, remaining, epoch_now() + 3_600, extra)
+    }
+
+    fn with_reset(
+        resource: &str,
+        remaining: u64,
+        reset: u64,
+        extra: &[(&'static str, &'static str)],
+    ) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-ratelimit-resource", resource.to_string()),
+            ("x-ratelimit-limit", "5000".to_string()),
+            ("x-ratelimit-remaining", remaining.to_string()),
+            ("x-ratelimit-reset", reset.to_string()),
+        ] {
+            headers.insert(name, HeaderValue::from_str(&value).unwrap());
+        }
+        for (name, value) in extra {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn acquire_rotates_to_the_token_with_most_budget() {
+        let pool = pool(&["first", "second"]);
+        pool.slots[0].record(&headers(CORE, 1_000, &[]));
+        pool.slots[1].record(&headers(CORE, 2_000, &[]));
+        assert_eq!(pool.acquire(CORE).await.token(), Some("token-second"));
+
+        pool.slots[1].record(&headers(CORE, 500, &[]));
+        assert_eq!(pool.acquire(CORE).await.token(), Some("token-first"));
+
+        // Budgets are per resource: no GraphQL budget is known yet for the
+        // second token.
+        pool.slots[0].record(&headers(GRAPHQL, 4_000, &[]));
+        assert_eq!(pool.acquire(GRAPHQL).await.token(), Some("token-second"));
+
+        let requests = pool
+            .stats()
+            .iter()
+            .map(|stats| stats.requests)
+            .collect::<Vec<_>>();
+        assert_eq!(requests, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_when_every_token_is_exhausted() {
+        let pool = pool(&["first", "second"]);
+        let reset = epoch_now() + 1;
+        for slot in pool.slots.iter() {
+            assert!(slot.record_rate_limited(CORE, &with_reset(CORE, 0, reset, &[]), ""));
+        }
+
+        let started = Instant::now();
+        pool.acquire(CORE).await;
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(epoch_now() > reset);
+    }
+
+    #[tokio::test]
+    async fn retry_after_pauses_the_token() {
+        let pool = pool(&["only"]);
+        let slot = pool.acquire(CORE).await;
+
+        assert!(!slot.record_rate_limited(CORE, &headers(CORE, 4_000, &[]), ""));
+        assert!(slot.record_rate_limited(CORE, &headers(CORE, 4_000, &[("retry-after", "1")]), ""));
+        assert_eq!(pool.stats()[0].rate_limited, 1);
+
+        let started = Instant::now();
+        assert_eq!(pool.acquire(GRAPHQL).await.token(), Some("token-only"));
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -9,6 +9,9 @@ pub struct Repository {
     pub language: Option<String>,
     pub clone_url: String,
     pub ssh_url: String,
+    /// Size reported by GitHub, in kilobytes.
+    #[serde(default)]
+    pub size: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,3 +29,27 @@ pub struct PullRequest {
     pub title: String,
     pub state: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueTree {
+    #[serde(flatten)]
+    pub issue: Issue,
+    pub labels: Vec<String>,
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequestTree {
+    #[serde(flatten)]
+    pub pull_request: PullRequest,
+    pub labels: Vec<String>,
+    pub comments: Vec<Comment>,
+}
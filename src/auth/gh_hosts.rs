@@ -0,0 +1,98 @@
+use std::{env, fs, path::PathBuf};
+
+/// Reads the token the GitHub CLI stored for `host` in its `hosts.yml`.
+/// Recent `gh` versions keep tokens in the system keyring instead, in which
+/// case the file has no `oauth_token` and `None` is returned.
+pub fn read_gh_token(host: &str) -> Option<String> {
+    let contents = fs::read_to_string(hosts_file()?).ok()?;
+    parse_hosts_token(&contents, host)
+}
+
+fn hosts_file() -> Option<PathBuf> {
+    let non_empty = |name| env::var_os(name).filter(|value| !value.is_empty());
+
+    let config_dir = if let Some(dir) = non_empty("GH_CONFIG_DIR") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = non_empty("XDG_CONFIG_HOME") {
+        PathBuf::from(dir).join("gh")
+    } else if cfg!(windows) {
+        PathBuf::from(non_empty("APPDATA")?).join("GitHub CLI")
+    } else {
+        PathBuf::from(non_empty("HOME")?).join(".config").join("gh")
+    };
+
+    Some(config_dir.join("hosts.yml"))
+}
+
+/// Extracts `<host>.oauth_token` from `hosts.yml` without a YAML parser; the
+/// file is a flat mapping of hosts to scalar settings plus a `users` map.
+fn parse_hosts_token(contents: &str, host: &str) -> Option<String> {
+    let mut in_host = false;
+    let mut child_indent = None;
+
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let indent = line.len() - trimmed.len();
+        if indent == 0 {
+            in_host = trimmed.trim_end().trim_end_matches(':').trim_matches('"') == host;
+            child_indent = None;
+            continue;
+        }
+        if !in_host {
+            continue;
+        }
+
+        // Only direct children of the host entry, not the nested `users` map.
+        if *child_indent.get_or_insert(indent) != indent {
+            continue;
+        }
+        if let Some(value) = trimmed.strip_prefix("oauth_token:") {
+            let token = value.trim().trim_matches('"').trim_matches('\'');
+            return (!token.is_empty()).then(|| token.to_string());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_hosts_token;
+
+    const HOSTS: &str = "\
+github.com:
+    users:
+        octocat:
+            oauth_token: gho_nested
+    oauth_token: gho_primary
+    user: octocat
+    git_protocol: https
+ghe.example.com:
+    oauth_token: \"gho_enterprise\"
+";
+
+    #[test]
+    fn reads_token_for_host() {
+        assert_eq!(
+            parse_hosts_token(HOSTS, "github.com").as_deref(),
+            Some("gho_primary")
+        );
+        assert_eq!(
+            parse_hosts_token(HOSTS, "ghe.example.com").as_deref(),
+            Some("gho_enterprise")
+        );
+    }
+
+    #[test]
+    fn ignores_missing_host_and_keyring_entries() {
+        assert_eq!(parse_hosts_token(HOSTS, "example.org"), None);
+        assert_eq!(
+            parse_hosts_token("github.com:\n    user: octocat\n", "github.com"),
+            None
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{debug, warn};
+
+/// Credential helpers may wait on a locked keyring or a network service.
+const FILL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Asks the configured git credential helpers for the password of
+/// `https://<host>`. Prompts are disabled and the helper is killed after
+/// [`FILL_TIMEOUT`], so this returns `None` rather than blocking when no
+/// helper has a stored credential.
+pub async fn read_git_credential(host: &str) -> Option<String> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .ok()?;
+
+    let request = format!("protocol=https\nhost={host}\n\n");
+    child
+        .stdin
+        .take()?
+        .write_all(request.as_bytes())
+        .await
+        .ok()?;
+
+    let output = match tokio::time::timeout(FILL_TIMEOUT, child.wait_with_output()).await {
+        Ok(output) => output.ok()?,
+        Err(_) => {
+            warn!(
+                host,
+                timeout_seconds = FILL_TIMEOUT.as_secs(),
+                "git credential fill timed out",
+            );
+            return None;
+        }
+    };
+    if !output.status.success() {
+        debug!(
+            host,
+            stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+            "git credential fill found no credential",
+        );
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .map(str::trim)
+        .filter(|password| !password.is_empty())
+        .map(ToString::to_string)
+}
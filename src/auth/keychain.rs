@@ -1,15 +1,364 @@
-use crate::{error::AuthError, Result};
+use std::{
+    env,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::debug;
+
+use crate::{
+    crypto::{self, EncryptionKey},
+    error::{AuthError, Result},
+    io::atomic_write::write_atomic,
+};
+
+pub const DEFAULT_SERVICE: &str = "github-backup-rs";
+const ACCOUNT: &str = "github-backup-rs";
+/// Secret store tools may wait on a locked keyring or an unlock prompt.
+const STORE_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Looks up the token stored for `service`, first in the platform secret
+/// store (macOS Keychain through `security`, the Secret Service over D-Bus
+/// through `secret-tool` elsewhere), then in the encrypted token file written
+/// by [`store_token_in_keychain`] when no secret store is available.
+pub async fn read_token_from_keychain(
+    service: Option<&str>,
+    key_file: Option<&Path>,
+) -> Result<Option<String>> {
+    let service = service.unwrap_or(DEFAULT_SERVICE);
+
+    match platform::lookup(service).await {
+        Ok(Some(token)) => return Ok(Some(token)),
+        Ok(None) => debug!(service, "no token in the platform secret store"),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            debug!(service, "platform secret store is not available")
+        }
+        Err(error) => {
+            return Err(AuthError::Keychain(format!("secret store lookup failed: {error}")).into())
+        }
+    }
+
+    let path = token_file_path(service)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let key = load_key(key_file, &path)?;
+    read_encrypted_token(&key, &path)
+}
+
+/// Saves `token` for `service` in the platform secret store, or in an
+/// encrypted file under the user configuration directory when none is
+/// available. Returns a description of where it was stored.
+pub async fn store_token_in_keychain(
+    service: Option<&str>,
+    token: &str,
+    key_file: Option<&Path>,
+) -> Result<String> {
+    let service = service.unwrap_or(DEFAULT_SERVICE);
+
+    match platform::store(service, token).await {
+        Ok(location) => return Ok(location),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            debug!(
+                service,
+                "platform secret store is not available, using a file"
+            )
+        }
+        Err(error) => {
+            return Err(AuthError::Keychain(format!("secret store update failed: {error}")).into())
+        }
+    }
+
+    let path = token_file_path(service)?;
+    let key = load_key(key_file, &path)?;
+    write_encrypted_token(&key, &path, token)?;
+    Ok(path.display().to_string())
+}
+
+fn read_encrypted_token(key: &EncryptionKey, path: &Path) -> Result<Option<String>> {
+    let plaintext = crypto::decrypt(key, &std::fs::read(path)?)?;
+    let token = String::from_utf8(plaintext)
+        .map_err(|_| AuthError::Keychain(format!("'{}' is not a token", path.display())))?;
+    Ok(Some(token.trim().to_string()).filter(|token| !token.is_empty()))
+}
+
+fn write_encrypted_token(key: &EncryptionKey, path: &Path, token: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_atomic(path, &crypto::encrypt(key, token.as_bytes()))?;
+    Ok(())
+}
+
+/// `$XDG_CONFIG_HOME/github-backup-rs/<service>.token.enc`, falling back to
+/// `~/.config`.
+fn token_file_path(service: &str) -> Result<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or_else(|| {
+            AuthError::Keychain("cannot locate the user configuration directory".to_string())
+        })?;
+
+    Ok(config_dir
+        .join("github-backup-rs")
+        .join(format!("{service}.token{}", crypto::ENCRYPTED_SUFFIX)))
+}
+
+fn load_key(key_file: Option<&Path>, token_file: &Path) -> Result<EncryptionKey> {
+    let key_file = key_file.ok_or_else(|| {
+        AuthError::Keychain(format!(
+            "'{}' needs --keychain-key-file to be read or written",
+            token_file.display()
+        ))
+    })?;
+    Ok(EncryptionKey::from_file(key_file)?)
+}
+
+/// Runs a secret store command, returning its trimmed output or `None` when it
+/// exits unsuccessfully, which both tools do when no item matches. Secrets are
+/// passed on stdin so they never appear in the process list. The command is
+/// killed after [`STORE_COMMAND_TIMEOUT`].
+async fn run_store_command(
+    program: &str,
+    args: &[&str],
+    stdin: Option<&str>,
+) -> std::io::Result<Option<String>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes()).await?;
+    }
+
+    let output = tokio::time::timeout(STORE_COMMAND_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{program} did not finish within {}s",
+                    STORE_COMMAND_TIMEOUT.as_secs()
+                ),
+            )
+        })??;
+    if !output.status.success() {
+        debug!(
+            program,
+            status = %output.status,
+            stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+            "secret store command failed",
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
 
 #[cfg(target_os = "macos")]
-pub fn read_token_from_keychain(service: Option<&str>) -> Result<Option<String>> {
-    let _service = service.unwrap_or("github-backup-rs");
-    Err(AuthError::Keychain(
-        "keychain lookup is planned but not implemented in this scaffold".to_string(),
-    )
-    .into())
+mod platform {
+    use std::io::Error;
+
+    use super::{run_store_command, ACCOUNT};
+
+    pub async fn lookup(service: &str) -> std::io::Result<Option<String>> {
+        Ok(run_store_command(
+            "security",
+            &["find-generic-password", "-s", service, "-a", ACCOUNT, "-w"],
+            None,
+        )
+        .await?
+        .filter(|token| !token.is_empty()))
+    }
+
+    /// `security add-generic-password -w` only accepts the password as an
+    /// argument, so the command is sent to `security -i` on stdin instead.
+    /// Interactive mode does not report failures in its exit status, so the
+    /// item is read back.
+    pub async fn store(service: &str, token: &str) -> std::io::Result<String> {
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            quote(service),
+            quote(ACCOUNT),
+            quote(token)
+        );
+        run_store_command("security", &["-i"], Some(&command))
+            .await?
+            .ok_or_else(|| Error::other("security add-generic-password failed"))?;
+        if lookup(service).await?.as_deref() != Some(token) {
+            return Err(Error::other("security add-generic-password failed"));
+        }
+        Ok(format!("macOS Keychain item '{service}'"))
+    }
+
+    fn quote(value: &str) -> String {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::io::Error;
+
+    use super::{run_store_command, ACCOUNT};
+
+    pub async fn lookup(service: &str) -> std::io::Result<Option<String>> {
+        Ok(run_store_command(
+            "secret-tool",
+            &["lookup", "service", service, "account", ACCOUNT],
+            None,
+        )
+        .await?
+        .filter(|token| !token.is_empty()))
+    }
+
+    pub async fn store(service: &str, token: &str) -> std::io::Result<String> {
+        let label = format!("GitHub token for {service}");
+        run_store_command(
+            "secret-tool",
+            &[
+                "store", "--label", &label, "service", service, "account", ACCOUNT,
+            ],
+            Some(token),
+        )
+        .await?
+        .ok_or_else(|| Error::other("secret-tool store failed"))?;
+        Ok(format!("Secret Service item '{service}'"))
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use std::io::{Error, ErrorKind};
+
+    pub async fn lookup(_service: &str) -> std::io::Result<Option<String>> {
+        Err(Error::from(ErrorKind::NotFound))
+    }
+
+    pub async fn store(_service: &str, _token: &str) -> std::io::Result<String> {
+        Err(Error::from(ErrorKind::NotFound))
+    }
 }
 
-#[cfg(not(target_os = "macos"))]
-pub fn read_token_from_keychain(_service: Option<&str>) -> Result<Option<String>> {
-    Ok(None)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=\n";
+
+    #[test]
+    fn token_file_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("key");
+        std::fs::write(&key_file, KEY).unwrap();
+        let key = EncryptionKey::from_file(&key_file).unwrap();
+        let path = dir.path().join("config/github-backup-rs/service.token.enc");
+
+        write_encrypted_token(&key, &path, "ghp_example").unwrap();
+        assert!(!std::fs::read(&path)
+            .unwrap()
+            .windows(11)
+            .any(|window| window == b"ghp_example"));
+        assert_eq!(
+            read_encrypted_token(&key, &path).unwrap().as_deref(),
+            Some("ghp_example")
+        );
+
+        std::fs::write(&key_file, "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=\n").unwrap();
+        let other = EncryptionKey::from_file(&key_file).unwrap();
+        assert!(read_encrypted_token(&other, &path).is_err());
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[tokio::test]
+    async fn reads_the_secret_service_then_the_token_file() {
+        let secret_tool = crate::test_support::FakeSecretTool::install().await;
+        secret_tool.set_item("stored", "ghp_secret_service");
+
+        assert_eq!(
+            read_token_from_keychain(Some("stored"), None)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("ghp_secret_service")
+        );
+        assert_eq!(
+            read_token_from_keychain(Some("missing"), None)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let key_file = secret_tool.config_dir().join("key");
+        std::fs::write(&key_file, KEY).unwrap();
+        let key = EncryptionKey::from_file(&key_file).unwrap();
+        let path = token_file_path("missing").unwrap();
+        assert!(path.starts_with(secret_tool.config_dir()));
+        write_encrypted_token(&key, &path, "ghp_file").unwrap();
+
+        assert_eq!(
+            read_token_from_keychain(Some("missing"), Some(&key_file))
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("ghp_file")
+        );
+        assert!(read_token_from_keychain(Some("missing"), None)
+            .await
+            .is_err());
+        assert_eq!(
+            secret_tool.calls(),
+            [
+                "lookup service stored account github-backup-rs",
+                "lookup service missing account github-backup-rs",
+                "lookup service missing account github-backup-rs",
+                "lookup service missing account github-backup-rs",
+            ]
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[tokio::test]
+    async fn store_token_saves_in_the_secret_service() {
+        let secret_tool = crate::test_support::FakeSecretTool::install().await;
+        let args = <crate::cli::args::CliArgs as clap::Parser>::parse_from([
+            "github-backup",
+            "--store-token",
+            "--token",
+            "ghp_stored",
+            "--keychain-service",
+            "backup",
+        ]);
+
+        crate::cli::run::run_cli(args).await.unwrap();
+        assert_eq!(secret_tool.item("backup").as_deref(), Some("ghp_stored"));
+        assert_eq!(
+            secret_tool.calls(),
+            ["store --label GitHub token for backup service backup account github-backup-rs"]
+        );
+        assert_eq!(
+            read_token_from_keychain(Some("backup"), None)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("ghp_stored")
+        );
+        assert!(!token_file_path("backup").unwrap().exists());
+    }
 }
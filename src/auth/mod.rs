@@ -1,27 +1,184 @@
 pub mod classic_pat;
 pub mod file_token;
 pub mod fine_grained;
+pub mod gh_hosts;
+pub mod git_credential;
 pub mod github_app;
 pub mod keychain;
+pub mod preflight;
 
-use crate::{config::AuthConfig, error::Result};
+use std::collections::HashSet;
+
+use tracing::info;
+
+use crate::{
+    config::AuthConfig,
+    error::{AuthError, Result},
+};
 
 pub trait AuthProvider {
     fn auth_header_value(&self) -> Result<String>;
 }
 
-pub fn resolve_token(config: &AuthConfig) -> Result<Option<String>> {
+/// A token and a description of where it came from that is safe to log.
+#[derive(Clone)]
+pub struct Credential {
+    pub source: String,
+    pub token: String,
+}
+
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("Credential")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+pub async fn resolve_token(config: &AuthConfig) -> Result<Option<String>> {
+    Ok(resolve_credentials(config)
+        .await?
+        .into_iter()
+        .next()
+        .map(|credential| credential.token))
+}
+
+/// Collects every configured token: `--token` first, then each
+/// `--token-file` in order. The keychain is only consulted when neither is
+/// given, and the credentials of other tools only when nothing was configured.
+/// Duplicate tokens are dropped.
+pub async fn resolve_credentials(config: &AuthConfig) -> Result<Vec<Credential>> {
+    let mut credentials = Vec::new();
+
     if let Some(token) = &config.token {
-        return Ok(Some(token.clone()));
+        credentials.push(Credential {
+            source: "--token".to_string(),
+            token: token.clone(),
+        });
+    }
+
+    for path in &config.token_files {
+        credentials.push(Credential {
+            source: path.display().to_string(),
+            token: file_token::read_token_file(path)?,
+        });
+    }
+
+    if credentials.is_empty() && config.use_keychain {
+        let service = config.keychain_service.as_deref();
+        let token =
+            keychain::read_token_from_keychain(service, config.keychain_key_file.as_deref())
+                .await?
+                .ok_or_else(|| {
+                    AuthError::Keychain(format!(
+                        "no token stored for service '{}'",
+                        service.unwrap_or(keychain::DEFAULT_SERVICE)
+                    ))
+                })?;
+        credentials.push(Credential {
+            source: "keychain".to_string(),
+            token,
+        });
+    }
+
+    if credentials.is_empty() && config.credential_fallbacks {
+        credentials.extend(fallback_credential(&config.host).await);
     }
 
-    if let Some(path) = &config.token_file {
-        return file_token::read_token_file(path).map(Some);
+    let mut seen = HashSet::new();
+    credentials.retain(|credential| seen.insert(credential.token.clone()));
+    Ok(credentials)
+}
+
+/// Reuses a token another tool already has for `host`, trying the GitHub CLI
+/// `hosts.yml`, then `git credential fill`, then `GH_TOKEN`.
+async fn fallback_credential(host: &str) -> Option<Credential> {
+    let credential = |source: &str, token: String| Credential {
+        source: source.to_string(),
+        token,
+    };
+
+    let mut found = gh_hosts::read_gh_token(host).map(|token| credential("gh hosts.yml", token));
+    if found.is_none() {
+        found = git_credential::read_git_credential(host)
+            .await
+            .map(|token| credential("git credential", token));
+    }
+    if found.is_none() {
+        found = std::env::var("GH_TOKEN")
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .map(|token| credential("GH_TOKEN", token));
     }
 
-    if config.use_keychain {
-        return keychain::read_token_from_keychain(config.keychain_service.as_deref());
+    if let Some(found) = &found {
+        info!(host, source = %found.source, "using token from another tool");
     }
+    found
+}
 
-    Ok(None)
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeSecretTool;
+
+    fn auth_config(token: Option<&str>, token_files: &[&std::path::Path]) -> AuthConfig {
+        AuthConfig {
+            token: token.map(str::to_string),
+            token_files: token_files.iter().map(|path| path.to_path_buf()).collect(),
+            use_keychain: true,
+            keychain_service: Some("backup".to_string()),
+            keychain_key_file: None,
+            credential_fallbacks: true,
+            host: "github.com".to_string(),
+            preflight: false,
+        }
+    }
+
+    fn sources(credentials: &[Credential]) -> Vec<(&str, &str)> {
+        credentials
+            .iter()
+            .map(|credential| (credential.source.as_str(), credential.token.as_str()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn resolves_tokens_before_the_keychain_and_the_keychain_before_fallbacks() {
+        let secret_tool = FakeSecretTool::install().await;
+        secret_tool.set_item("backup", "ghp_keychain");
+        let first = secret_tool.config_dir().join("first");
+        let second = secret_tool.config_dir().join("second");
+        std::fs::write(&first, "ghp_file\n").unwrap();
+        std::fs::write(&second, "ghp_token\n").unwrap();
+
+        let config = auth_config(Some("ghp_token"), &[&first, &second]);
+        let credentials = resolve_credentials(&config).await.unwrap();
+        assert_eq!(
+            sources(&credentials),
+            [
+                ("--token", "ghp_token"),
+                (first.to_str().unwrap(), "ghp_file")
+            ]
+        );
+        assert!(secret_tool.calls().is_empty());
+
+        let credentials = resolve_credentials(&auth_config(None, &[])).await.unwrap();
+        assert_eq!(sources(&credentials), [("keychain", "ghp_keychain")]);
+
+        // A keychain without the token fails instead of borrowing one.
+        let config = AuthConfig {
+            keychain_service: Some("missing".to_string()),
+            ..auth_config(None, &[])
+        };
+        let error = resolve_credentials(&config).await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("no token stored for service 'missing'"),
+            "{error}"
+        );
+        assert_eq!(secret_tool.calls().len(), 2);
+    }
 }
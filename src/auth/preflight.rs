@@ -0,0 +1,567 @@
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    api::client::{extract_legal_url, GitHubClient},
+    auth::Credential,
+    config::{BackupConfig, BackupScope},
+    error::{ApiError, AuthError, Result},
+};
+
+/// Feature whose availability the preflight check reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    RepositoryListing,
+    PrivateRepositories,
+    Issues,
+    Wikis,
+    OrganizationMembers,
+}
+
+impl Feature {
+    fn label(self) -> &'static str {
+        match self {
+            Self::RepositoryListing => "repository listing",
+            Self::PrivateRepositories => "private repositories",
+            Self::Issues => "issues",
+            Self::Wikis => "wikis",
+            Self::OrganizationMembers => "organization members",
+        }
+    }
+
+    /// The backup cannot do anything useful without these.
+    fn is_essential(self) -> bool {
+        matches!(self, Self::RepositoryListing)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    Available,
+    /// Works for public data only.
+    Limited,
+    Unavailable,
+    /// Cannot be determined for this kind of token.
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureCheck {
+    pub feature: Feature,
+    pub availability: Availability,
+    pub detail: String,
+}
+
+/// Checks what each configured token can access before the backup starts,
+/// logs one line per feature and fails when an essential feature is missing.
+///
+/// Classic tokens report their scopes in `X-OAuth-Scopes`; fine-grained and
+/// GitHub App tokens do not, so representative endpoints are probed instead.
+pub async fn run_preflight(config: &BackupConfig, credentials: &[Credential]) -> Result<()> {
+    if credentials.is_empty() {
+        info!("no token configured, only public repositories can be backed up");
+        return Ok(());
+    }
+
+    // Scope headers are not kept in the response cache, so always ask GitHub.
+    let mut runtime = config.runtime.clone();
+    runtime.http_cache_dir = None;
+    // A rate limit only makes a check unknown; waiting for it is left to the
+    // backup.
+    runtime.max_retries = 0;
+
+    for credential in credentials {
+        let client = GitHubClient::from_runtime(&runtime, vec![credential.clone()])?;
+        let checks = check_token(config, &client, credential).await?;
+
+        for check in &checks {
+            match check.availability {
+                Availability::Available => info!(
+                    token = %credential.source,
+                    feature = check.feature.label(),
+                    detail = %check.detail,
+                    "preflight: available",
+                ),
+                Availability::Limited => info!(
+                    token = %credential.source,
+                    feature = check.feature.label(),
+                    detail = %check.detail,
+                    "preflight: limited",
+                ),
+                Availability::Unknown => info!(
+                    token = %credential.source,
+                    feature = check.feature.label(),
+                    detail = %check.detail,
+                    "preflight: not verifiable",
+                ),
+                Availability::Unavailable => warn!(
+                    token = %credential.source,
+                    feature = check.feature.label(),
+                    detail = %check.detail,
+                    "preflight: unavailable",
+                ),
+            }
+        }
+
+        let missing = checks
+            .iter()
+            .filter(|check| {
+                check.feature.is_essential() && check.availability == Availability::Unavailable
+            })
+            .map(|check| format!("{} ({})", check.feature.label(), check.detail))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(AuthError::InsufficientPermissions {
+                token: credential.source.clone(),
+                missing: missing.join(", "),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_token(
+    config: &BackupConfig,
+    client: &GitHubClient,
+    credential: &Credential,
+) -> Result<Vec<FeatureCheck>> {
+    let (login, scopes) = match client
+        .get_json_with_headers::<AuthenticatedUser>("/user")
+        .await
+    {
+        Ok((user, headers)) => (Some(user.login), oauth_scopes(&headers)),
+        Err(ApiError::UnexpectedStatus { status, .. }) if status == StatusCode::UNAUTHORIZED => {
+            return Err(AuthError::InsufficientPermissions {
+                token: credential.source.clone(),
+                missing: "token was rejected as invalid or expired".to_string(),
+            }
+            .into());
+        }
+        // GitHub App installation tokens cannot read `/user`.
+        Err(ApiError::UnexpectedStatus { status, .. }) if status == StatusCode::FORBIDDEN => {
+            (None, None)
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    info!(
+        token = %credential.source,
+        login = login.as_deref().unwrap_or("<app installation>"),
+        kind = if scopes.is_some() { "classic" } else { "fine-grained or app" },
+        scopes = scopes.as_ref().map(|scopes| scopes.join(" ")),
+        "preflight: token accepted",
+    );
+
+    let mut checks = vec![check_listing(config, client, login.as_deref()).await?];
+    match &scopes {
+        Some(scopes) => checks.extend(classic_checks(config, scopes)),
+        None => checks.extend(probe_checks(config, client, login.as_deref()).await?),
+    }
+
+    Ok(checks)
+}
+
+fn classic_checks(config: &BackupConfig, scopes: &[String]) -> Vec<FeatureCheck> {
+    let has = |scope: &str| scopes.iter().any(|granted| granted == scope);
+    let repo = has("repo");
+    let repository_access = |feature| FeatureCheck {
+        feature,
+        availability: if repo {
+            Availability::Available
+        } else {
+            Availability::Limited
+        },
+        detail: if repo {
+            "granted by the repo scope".to_string()
+        } else {
+            "public repositories only, the repo scope is missing".to_string()
+        },
+    };
+
+    let mut checks = vec![
+        FeatureCheck {
+            feature: Feature::PrivateRepositories,
+            availability: if repo {
+                Availability::Available
+            } else {
+                Availability::Unavailable
+            },
+            detail: if repo {
+                "granted by the repo scope".to_string()
+            } else {
+                "the repo scope is missing".to_string()
+            },
+        },
+        repository_access(Feature::Issues),
+        repository_access(Feature::Wikis),
+    ];
+
+    if let BackupScope::Organization(_) = config.scope {
+        let read_org = has("read:org") || has("write:org") || has("admin:org");
+        checks.push(FeatureCheck {
+            feature: Feature::OrganizationMembers,
+            availability: if read_org {
+                Availability::Available
+            } else {
+                Availability::Limited
+            },
+            detail: if read_org {
+                "granted by an org scope".to_string()
+            } else {
+                "public members only, the read:org scope is missing".to_string()
+            },
+        });
+    }
+
+    checks
+}
+
+async fn probe_checks(
+    config: &BackupConfig,
+    client: &GitHubClient,
+    login: Option<&str>,
+) -> Result<Vec<FeatureCheck>> {
+    let private_path = match &config.scope {
+        BackupScope::Organization(org) => {
+            Some(format!("/orgs/{org}/repos?type=private&per_page=1"))
+        }
+        BackupScope::User(user) if login.is_some_and(|login| login.eq_ignore_ascii_case(user)) => {
+            Some("/user/repos?visibility=private&per_page=1".to_string())
+        }
+        _ => None,
+    };
+
+    let private = match private_path {
+        Some(path) => match probe(client, &path).await? {
+            Probe::Readable(Value::Array(items)) if !items.is_empty() => FeatureCheck {
+                feature: Feature::PrivateRepositories,
+                availability: Availability::Available,
+                detail: "private repositories are visible".to_string(),
+            },
+            Probe::Readable(_) => FeatureCheck {
+                feature: Feature::PrivateRepositories,
+                availability: Availability::Limited,
+                detail: "no private repositories are visible to this token".to_string(),
+            },
+            Probe::Denied => FeatureCheck {
+                feature: Feature::PrivateRepositories,
+                availability: Availability::Unavailable,
+                detail: "the token cannot list private repositories".to_string(),
+            },
+            Probe::RateLimited => FeatureCheck {
+                feature: Feature::PrivateRepositories,
+                availability: Availability::Unknown,
+                detail: "rate limited before private repositories could be listed".to_string(),
+            },
+        },
+        None => FeatureCheck {
+            feature: Feature::PrivateRepositories,
+            availability: Availability::Unknown,
+            detail: "only repositories granted to the token are visible".to_string(),
+        },
+    };
+
+    let mut checks = vec![
+        private,
+        FeatureCheck {
+            feature: Feature::Issues,
+            availability: Availability::Unknown,
+            detail: "requires the Issues read permission on each repository".to_string(),
+        },
+        FeatureCheck {
+            feature: Feature::Wikis,
+            availability: Availability::Unknown,
+            detail: "requires the Contents read permission on each repository".to_string(),
+        },
+    ];
+
+    if let BackupScope::Organization(org) = &config.scope {
+        let (availability, detail) = match probe(client, &format!("/orgs/{org}/members?per_page=1"))
+            .await?
+        {
+            Probe::Readable(_) => (Availability::Available, "organization members are readable"),
+            Probe::Denied => (
+                Availability::Unavailable,
+                "requires the Members read organization permission",
+            ),
+            Probe::RateLimited => (
+                Availability::Unknown,
+                "rate limited before organization members could be read",
+            ),
+        };
+        checks.push(FeatureCheck {
+            feature: Feature::OrganizationMembers,
+            availability,
+            detail: detail.to_string(),
+        });
+    }
+
+    Ok(checks)
+}
+
+async fn check_listing(
+    config: &BackupConfig,
+    client: &GitHubClient,
+    login: Option<&str>,
+) -> Result<FeatureCheck> {
+    let paths = match &config.scope {
+        BackupScope::User(user) if login.is_some_and(|login| login.eq_ignore_ascii_case(user)) => {
+            vec!["/user/repos?per_page=1".to_string()]
+        }
+        BackupScope::User(user) => vec![format!("/users/{user}/repos?per_page=1")],
+        BackupScope::Organization(org) => vec![format!("/orgs/{org}/repos?per_page=1")],
+        BackupScope::Repositories(repositories) => repositories
+            .iter()
+            .map(|repository| format!("/repos/{}", repository.trim_matches('/')))
+            .collect(),
+        BackupScope::Unknown => Vec::new(),
+    };
+
+    let mut inaccessible = Vec::new();
+    let mut rate_limited = Vec::new();
+    for path in &paths {
+        match probe(client, path).await? {
+            Probe::Readable(_) => {}
+            Probe::Denied => inaccessible.push(path.clone()),
+            Probe::RateLimited => rate_limited.push(path.clone()),
+        }
+    }
+
+    Ok(if !inaccessible.is_empty() {
+        FeatureCheck {
+            feature: Feature::RepositoryListing,
+            availability: Availability::Unavailable,
+            detail: format!("cannot read {}", inaccessible.join(", ")),
+        }
+    } else if !rate_limited.is_empty() {
+        FeatureCheck {
+            feature: Feature::RepositoryListing,
+            availability: Availability::Unknown,
+            detail: format!("rate limited while reading {}", rate_limited.join(", ")),
+        }
+    } else {
+        FeatureCheck {
+            feature: Feature::RepositoryListing,
+            availability: Availability::Available,
+            detail: "the backup target is readable".to_string(),
+        }
+    })
+}
+
+/// Result of requesting a representative endpoint.
+#[derive(Debug, PartialEq)]
+enum Probe {
+    Readable(Value),
+    /// Access is denied or the resource is hidden from the token.
+    Denied,
+    /// GitHub refused the request because of a rate limit, so access is
+    /// unknown.
+    RateLimited,
+}
+
+async fn probe(client: &GitHubClient, path: &str) -> Result<Probe> {
+    match client.get_json::<Value>(path).await {
+        Ok(value) => Ok(Probe::Readable(value)),
+        // Legally blocked repositories are skipped during the backup itself,
+        // whether GitHub answers 451 or 403 with a block notice.
+        Err(ApiError::UnexpectedStatus { status, .. })
+            if status == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS =>
+        {
+            Ok(Probe::Readable(Value::Null))
+        }
+        Err(ApiError::UnexpectedStatus { status, message })
+            if status == StatusCode::FORBIDDEN && extract_legal_url(&message).is_some() =>
+        {
+            Ok(Probe::Readable(Value::Null))
+        }
+        Err(ApiError::UnexpectedStatus { status, message })
+            if status == StatusCode::TOO_MANY_REQUESTS
+                || (status == StatusCode::FORBIDDEN && is_rate_limit_message(&message)) =>
+        {
+            Ok(Probe::RateLimited)
+        }
+        Err(ApiError::RetriesExhausted(_)) => Ok(Probe::RateLimited),
+        Err(ApiError::UnexpectedStatus { status, .. })
+            if status == StatusCode::FORBIDDEN || status == StatusCode::NOT_FOUND =>
+        {
+            Ok(Probe::Denied)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// GitHub answers primary and secondary rate limits with 403 and a message
+/// such as "API rate limit exceeded" or "You have exceeded a secondary rate
+/// limit".
+fn is_rate_limit_message(message: &str) -> bool {
+    let message = serde_json::from_str::<Value>(message)
+        .ok()
+        .and_then(|value| value.get("message")?.as_str().map(ToString::to_string))
+        .unwrap_or_else(|| message.to_string());
+    message.to_ascii_lowercase().contains("rate limit")
+}
+
+fn oauth_scopes(headers: &HeaderMap) -> Option<Vec<String>> {
+    let value = headers.get("x-oauth-scopes")?.to_str().ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthenticatedUser {
+    login: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Response, TestServer};
+
+    async fn server() -> TestServer {
+        TestServer::start(|request| match request.path.as_str() {
+            "/repos/owner/app" | "/orgs/owner/repos?per_page=1" => {
+                Response::json(200, r#"{"name":"app"}"#)
+            }
+            "/repos/owner/blocked" => Response::json(
+                451,
+                r#"{"message":"Repository access blocked","block":{"html_url":"https://github.com/github/dmca/1"}}"#,
+            ),
+            "/repos/owner/takedown" => Response::json(
+                403,
+                r#"{"message":"Repository access blocked","block":{"html_url":"https://github.com/github/dmca/2"}}"#,
+            ),
+            "/repos/owner/busy" => Response::json(
+                403,
+                r#"{"message":"You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#,
+            ),
+            "/repos/owner/throttled" => Response::new(429),
+            "/repos/owner/forbidden" => {
+                Response::json(403, r#"{"message":"Resource not accessible by integration"}"#)
+            }
+            _ => Response::json(404, r#"{"message":"Not Found"}"#),
+        })
+        .await
+    }
+
+    fn config(server: &TestServer, target: &[&str]) -> BackupConfig {
+        let mut args = vec![
+            "--api-base-url",
+            &server.url,
+            "--no-http-cache",
+            "--max-retries",
+            "0",
+        ];
+        args.extend(target);
+        test_support::config(&args)
+    }
+
+    fn client(config: &BackupConfig) -> GitHubClient {
+        GitHubClient::from_runtime(&config.runtime, Vec::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn probe_classifies_responses() {
+        let server = server().await;
+        let client = client(&config(&server, &["owner"]));
+
+        assert!(matches!(
+            probe(&client, "/repos/owner/app").await.unwrap(),
+            Probe::Readable(Value::Object(_))
+        ));
+        for path in ["/repos/owner/blocked", "/repos/owner/takedown"] {
+            assert_eq!(
+                probe(&client, path).await.unwrap(),
+                Probe::Readable(Value::Null),
+                "{path}"
+            );
+        }
+        for path in ["/repos/owner/busy", "/repos/owner/throttled"] {
+            assert_eq!(
+                probe(&client, path).await.unwrap(),
+                Probe::RateLimited,
+                "{path}"
+            );
+        }
+        for path in ["/repos/owner/forbidden", "/repos/owner/missing"] {
+            assert_eq!(probe(&client, path).await.unwrap(), Probe::Denied, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_is_available_when_every_target_is_readable() {
+        let server = server().await;
+        let config = config(
+            &server,
+            &[
+                "--repo",
+                "owner/app",
+                "--repo",
+                "owner/blocked",
+                "--repo",
+                "owner/takedown",
+            ],
+        );
+
+        let check = check_listing(&config, &client(&config), None)
+            .await
+            .unwrap();
+        assert_eq!(check.availability, Availability::Available);
+
+        let config = self::config(&server, &["owner", "--organization"]);
+        let check = check_listing(&config, &client(&config), None)
+            .await
+            .unwrap();
+        assert_eq!(check.availability, Availability::Available);
+    }
+
+    #[tokio::test]
+    async fn listing_is_unknown_when_rate_limited() {
+        let server = server().await;
+        let config = config(&server, &["--repo", "owner/app", "--repo", "owner/busy"]);
+
+        let check = check_listing(&config, &client(&config), None)
+            .await
+            .unwrap();
+        assert_eq!(check.availability, Availability::Unknown);
+        assert!(
+            check.detail.contains("/repos/owner/busy"),
+            "{}",
+            check.detail
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_is_unavailable_when_a_target_is_denied() {
+        let server = server().await;
+        let config = config(
+            &server,
+            &[
+                "--repo",
+                "owner/busy",
+                "--repo",
+                "owner/forbidden",
+                "--repo",
+                "owner/missing",
+            ],
+        );
+
+        let check = check_listing(&config, &client(&config), None)
+            .await
+            .unwrap();
+        assert_eq!(check.availability, Availability::Unavailable);
+        assert_eq!(
+            check.detail,
+            "cannot read /repos/owner/forbidden, /repos/owner/missing"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::{io::Write, path::Path, time::Duration};
+
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    api::types::Repository,
+    config::{ArchiveFormat, BackupConfig},
+    error::Result,
+    git::subprocess,
+    incremental::state::RepositoryState,
+    io::atomic_write::write_atomic_with,
+    storage::Storage,
+};
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Packages a clone as a single archive file under `archives/`, skipping the
+/// work when the refs are unchanged since the previous archive. Returns
+/// whether a new archive was written.
+pub async fn write_archive(
+    config: &BackupConfig,
+    storage: &Storage,
+    repository: &Repository,
+    clone_dir: &Path,
+    format: ArchiveFormat,
+    entry: &mut RepositoryState,
+) -> Result<bool> {
+    let refs = subprocess::list_refs(clone_dir, &[]).await?;
+    if refs.is_empty() {
+        return Ok(false);
+    }
+
+    let digest = refs_digest(format, &refs);
+    let key = archive_key(repository, format);
+    if entry.archive_refs_digest.as_deref() == Some(digest.as_str()) && storage.exists(&key).await?
+    {
+        return Ok(false);
+    }
+
+    let path = storage.staging_path(&key);
+    match format {
+        ArchiveFormat::Bundle => {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            subprocess::create_bundle(
+                clone_dir,
+                &path,
+                Duration::from_secs(config.runtime.git_timeout_seconds),
+            )
+            .await?;
+        }
+        ArchiveFormat::TarZst => {
+            let (path, name, clone_dir) = (
+                path.clone(),
+                repository.name.clone(),
+                clone_dir.to_path_buf(),
+            );
+            tokio::task::spawn_blocking(move || {
+                write_atomic_with(&path, |file| {
+                    let encoder = zstd::Encoder::new(file, ZSTD_LEVEL)?;
+                    let mut builder = tar::Builder::new(encoder);
+                    builder.follow_symlinks(false);
+                    builder.append_dir_all(&name, &clone_dir)?;
+                    builder.into_inner()?.finish()?.flush()
+                })
+            })
+            .await
+            .map_err(std::io::Error::other)??;
+        }
+    }
+
+    storage.put_file(&key, &path).await?;
+
+    info!(repo = %repository.full_name, path = %storage.describe(&key), "wrote repository archive");
+    entry.archive_refs_digest = Some(digest);
+    Ok(true)
+}
+
+/// Storage key of a repository archive, relative to the backup root.
+pub fn archive_key(repository: &Repository, format: ArchiveFormat) -> String {
+    let (owner, repo_name) = repository
+        .full_name
+        .split_once('/')
+        .unwrap_or(("unknown", repository.name.as_str()));
+
+    format!("archives/{owner}/{repo_name}.{}", format.extension())
+}
+
+fn refs_digest(format: ArchiveFormat, refs: &[(String, String)]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format.extension());
+    for (oid, name) in refs {
+        hasher.update(oid);
+        hasher.update(b" ");
+        hasher.update(name);
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+    use crate::{
+        config::{CloneMode, UpdateStrategy},
+        test_support::{self, git},
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    struct Fixture {
+        dir: tempfile::TempDir,
+        work: PathBuf,
+        clone: PathBuf,
+        repository: Repository,
+        entry: RepositoryState,
+    }
+
+    /// A bare remote with one commit, a working copy pushing to it and a
+    /// clone of it, backed up as `owner/app`.
+    async fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let work = dir.path().join("work");
+        let clone = dir.path().join("clone");
+        git(
+            dir.path(),
+            &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+        );
+        git(dir.path(), &["clone", "--quiet", "remote.git", "work"]);
+        git(&work, &["checkout", "--quiet", "-b", "main"]);
+        commit(&work, "one");
+
+        let url = format!("file://{}", dir.path().join("remote.git").display());
+        subprocess::clone_repository(&url, &clone, &CloneMode::default(), TIMEOUT)
+            .await
+            .unwrap();
+        Fixture {
+            dir,
+            work,
+            clone,
+            repository: Repository {
+                id: 1,
+                name: "app".to_string(),
+                full_name: "owner/app".to_string(),
+                archived: false,
+                language: None,
+                clone_url: url,
+                ssh_url: "git@github.com:owner/app.git".to_string(),
+                size: 0,
+            },
+            entry: RepositoryState {
+                full_name: "owner/app".to_string(),
+                path: "owner/app".to_string(),
+                archive_refs_digest: None,
+                clone_mode: CloneMode::default(),
+                synced_at: None,
+                maintained_at: None,
+            },
+        }
+    }
+
+    fn commit(work: &Path, content: &str) {
+        fs::write(work.join("file"), content).unwrap();
+        git(work, &["add", "file"]);
+        git(work, &["commit", "--quiet", "-m", content]);
+        git(work, &["push", "--quiet", "origin", "main"]);
+    }
+
+    async fn archive(fixture: &mut Fixture, format: ArchiveFormat) -> (bool, PathBuf) {
+        let output = fixture.dir.path().join("output");
+        let config = test_support::config(&["owner", "-o", output.to_str().unwrap()]);
+        let storage = Storage::from_config(&config).unwrap();
+        let written = write_archive(
+            &config,
+            &storage,
+            &fixture.repository,
+            &fixture.clone,
+            format,
+            &mut fixture.entry,
+        )
+        .await
+        .unwrap();
+        (
+            written,
+            output.join(archive_key(&fixture.repository, format)),
+        )
+    }
+
+    #[tokio::test]
+    async fn bundles_are_rebuilt_only_when_refs_move() {
+        let mut fixture = fixture().await;
+
+        let (written, path) = archive(&mut fixture, ArchiveFormat::Bundle).await;
+        assert!(written);
+        assert!(path.ends_with("archives/owner/app.bundle"));
+        let heads = git(
+            fixture.dir.path(),
+            &["bundle", "list-heads", path.to_str().unwrap()],
+        );
+        assert!(heads.contains("refs/heads/main"), "{heads}");
+        let digest = fixture.entry.archive_refs_digest.clone().unwrap();
+
+        assert!(!archive(&mut fixture, ArchiveFormat::Bundle).await.0);
+        assert_eq!(fixture.entry.archive_refs_digest.as_ref(), Some(&digest));
+
+        commit(&fixture.work, "two");
+        subprocess::update_repository(
+            &fixture.clone,
+            &CloneMode::default(),
+            false,
+            UpdateStrategy::FastForward,
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert!(archive(&mut fixture, ArchiveFormat::Bundle).await.0);
+        assert_ne!(fixture.entry.archive_refs_digest.as_ref(), Some(&digest));
+        let tip = git(&fixture.work, &["rev-parse", "HEAD"]);
+        let heads = git(
+            fixture.dir.path(),
+            &["bundle", "list-heads", path.to_str().unwrap()],
+        );
+        assert!(heads.contains(&tip), "{heads}");
+    }
+
+    #[tokio::test]
+    async fn tar_zst_archives_hold_the_working_tree_and_git_dir() {
+        let mut fixture = fixture().await;
+
+        let (written, path) = archive(&mut fixture, ArchiveFormat::TarZst).await;
+        assert!(written);
+        assert!(path.ends_with("archives/owner/app.tar.zst"));
+        let decoder = zstd::Decoder::new(fs::File::open(&path).unwrap()).unwrap();
+        let entries = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            entries.iter().any(|entry| entry == "app/file"),
+            "{entries:?}"
+        );
+        assert!(
+            entries.iter().any(|entry| entry == "app/.git/HEAD"),
+            "{entries:?}"
+        );
+
+        // Bundle and tar.zst digests differ, so switching formats rebuilds.
+        assert!(!archive(&mut fixture, ArchiveFormat::TarZst).await.0);
+        assert!(archive(&mut fixture, ArchiveFormat::Bundle).await.0);
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::info;
+
+use crate::{api::types::Repository, error::Result, storage::Storage};
+
+/// Repositories listed by a run, stored as separate arrays so
+/// `repositories.json` keeps the format of v1.0.0.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub repositories: Vec<Repository>,
+    pub orphaned: Vec<OrphanedRepository>,
+    pub submodules: Vec<SubmoduleRepository>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanedRepository {
+    pub id: u64,
+    pub full_name: String,
+    pub detected_at: String,
+    pub action: OrphanAction,
+    /// Where the clone lives now, relative to the `repositories/` directory.
+    pub path: Option<String>,
+}
+
+/// Repository referenced as a submodule and backed up with
+/// `--backup-submodules`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmoduleRepository {
+    pub url: String,
+    /// Where the clone lives, relative to the `repositories/` directory.
+    pub path: String,
+    /// Backed-up repositories declaring the submodule.
+    pub referenced_by: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanAction {
+    Kept,
+    Archived,
+    Deleted,
+}
+
+pub const INVENTORY_KEY: &str = "repositories.json";
+pub const ORPHANED_KEY: &str = "orphaned.json";
+pub const SUBMODULES_KEY: &str = "submodules.json";
+
+const INVENTORY_KEYS: [&str; 3] = [INVENTORY_KEY, ORPHANED_KEY, SUBMODULES_KEY];
+
+impl Inventory {
+    /// Writes each list that changed since the previous run. Returns whether
+    /// any was written.
+    pub async fn write(&self, storage: &Storage) -> Result<bool> {
+        let repositories = storage
+            .write_json_if_changed(INVENTORY_KEY, &self.repositories)
+            .await?;
+        let orphaned = storage
+            .write_json_if_changed(ORPHANED_KEY, &self.orphaned)
+            .await?;
+        let submodules = storage
+            .write_json_if_changed(SUBMODULES_KEY, &self.submodules)
+            .await?;
+        Ok(repositories || orphaned || submodules)
+    }
+}
+
+/// Loads the inventory written by a previous run. With encryption on, an
+/// unencrypted inventory written before it was turned on is read instead when
+/// no encrypted one exists yet; [`remove_unencrypted_inventory`] deletes it
+/// once the encrypted inventory is stored.
+pub async fn load_inventory(storage: &Storage) -> Result<Inventory> {
+    Ok(Inventory {
+        repositories: read_list(storage, INVENTORY_KEY).await?,
+        orphaned: read_list(storage, ORPHANED_KEY).await?,
+        submodules: read_list(storage, SUBMODULES_KEY).await?,
+    })
+}
+
+async fn read_list<T: DeserializeOwned>(storage: &Storage, key: &str) -> Result<Vec<T>> {
+    let bytes = match storage.read(key).await? {
+        Some(bytes) => bytes,
+        None => match storage.read_unencrypted(key).await? {
+            Some(bytes) => {
+                info!(
+                    path = key,
+                    "migrating the unencrypted inventory of an earlier run"
+                );
+                bytes
+            }
+            None => return Ok(Vec::new()),
+        },
+    };
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Deletes the unencrypted inventory of a run made before encryption was
+/// turned on, which would otherwise keep listing every repository in plain
+/// text.
+pub async fn remove_unencrypted_inventory(storage: &Storage) -> Result<()> {
+    for key in INVENTORY_KEYS {
+        storage.remove_unencrypted(key).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn migrates_unencrypted_inventory_when_encryption_is_turned_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("backup.key");
+        std::fs::write(&key_file, "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=\n").unwrap();
+        let output = dir.path().to_str().unwrap();
+        let plaintext = dir.path().join(ORPHANED_KEY);
+        std::fs::write(dir.path().join(INVENTORY_KEY), "[]").unwrap();
+        std::fs::write(
+            &plaintext,
+            r#"[{"id":7,"full_name":"octocat/old","detected_at":"2024-01-01T00:00:00Z","action":"kept","path":null}]"#,
+        )
+        .unwrap();
+
+        let plain =
+            Storage::from_config(&test_support::config(&["octocat", "-o", output])).unwrap();
+        remove_unencrypted_inventory(&plain).await.unwrap();
+        assert!(plaintext.exists());
+
+        let storage = Storage::from_config(&test_support::config(&[
+            "octocat",
+            "-o",
+            output,
+            "--encryption-key-file",
+            key_file.to_str().unwrap(),
+        ]))
+        .unwrap();
+        let inventory = load_inventory(&storage).await.unwrap();
+        assert_eq!(inventory.orphaned[0].full_name, "octocat/old");
+
+        assert!(inventory.write(&storage).await.unwrap());
+        remove_unencrypted_inventory(&storage).await.unwrap();
+        assert!(!plaintext.exists());
+        assert!(!dir.path().join(INVENTORY_KEY).exists());
+        assert_eq!(load_inventory(&storage).await.unwrap().orphaned[0].id, 7);
+    }
+}
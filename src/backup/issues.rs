@@ -0,0 +1,162 @@
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    api::{
+        graphql::GraphQlClient,
+        types::{IssueTree, PullRequestTree, Repository},
+    },
+    error::Result,
+    shutdown,
+    storage::Storage,
+};
+
+/// Issues and pull requests of one repository, as stored under `issues/`.
+#[derive(Debug, Serialize)]
+struct IssueBackup {
+    issues: Vec<IssueTree>,
+    pull_requests: Vec<PullRequestTree>,
+}
+
+/// Writes the issues and pull requests of every repository, with their
+/// labels and comments, to `issues/<owner>/<repo>.json`. A repository whose
+/// issues cannot be fetched is logged and skipped, keeping its previous file.
+/// Returns the number of files that changed.
+pub async fn backup_issues(
+    client: &GraphQlClient,
+    storage: &Storage,
+    repositories: &[Repository],
+) -> usize {
+    let mut written = 0;
+    for repository in repositories {
+        if shutdown::is_requested() {
+            break;
+        }
+
+        match backup_repository_issues(client, storage, repository).await {
+            Ok(true) => written += 1,
+            Ok(false) => {}
+            Err(error) => {
+                warn!(repo = %repository.full_name, error = %error, "failed backing up issues, continuing");
+            }
+        }
+    }
+
+    info!(
+        repositories = repositories.len(),
+        written, "issue and pull request backup finished"
+    );
+    written
+}
+
+async fn backup_repository_issues(
+    client: &GraphQlClient,
+    storage: &Storage,
+    repository: &Repository,
+) -> Result<bool> {
+    let (owner, name) = repository
+        .full_name
+        .split_once('/')
+        .unwrap_or(("unknown", repository.name.as_str()));
+    let backup = IssueBackup {
+        issues: client.list_issue_trees(owner, name).await?,
+        pull_requests: client.list_pull_request_trees(owner, name).await?,
+    };
+
+    storage
+        .write_json_if_changed(&issues_key(repository), &backup)
+        .await
+}
+
+/// Storage key of a repository's issues, relative to the backup root.
+pub fn issues_key(repository: &Repository) -> String {
+    let (owner, name) = repository
+        .full_name
+        .split_once('/')
+        .unwrap_or(("unknown", repository.name.as_str()));
+    format!("issues/{owner}/{name}.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        auth::Credential,
+        test_support::{self, Response, TestServer},
+    };
+
+    fn repository(full_name: &str) -> Repository {
+        Repository {
+            id: 1,
+            name: full_name.split_once('/').unwrap().1.to_string(),
+            full_name: full_name.to_string(),
+            archived: false,
+            language: None,
+            clone_url: format!("https://github.com/{full_name}.git"),
+            ssh_url: format!("git@github.com:{full_name}.git"),
+            size: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_issues_and_pull_requests_per_repository() {
+        let server = TestServer::start(|request| {
+            let body = serde_json::from_slice::<Value>(&request.body).unwrap();
+            if body["variables"]["name"] == "missing" {
+                return Response::json(200, r#"{"errors":[{"message":"Could not resolve"}]}"#);
+            }
+            let connection = if body["query"].as_str().unwrap().contains("pullRequests(") {
+                "pullRequests"
+            } else {
+                "issues"
+            };
+            let node = json!({
+                "databaseId": 10,
+                "number": 1,
+                "title": format!("first of {connection}"),
+                "state": "CLOSED",
+                "labels": { "nodes": [] },
+                "comments": { "pageInfo": { "hasNextPage": false }, "nodes": [] },
+            });
+            let data = json!({ "data": { "repository": { connection: {
+                "pageInfo": { "hasNextPage": false },
+                "nodes": [node],
+            }}}});
+            Response::json(200, &data.to_string())
+        })
+        .await;
+        let output = tempfile::tempdir().unwrap();
+        let config = test_support::config(&[
+            "acme",
+            "-o",
+            output.path().to_str().unwrap(),
+            "--api-base-url",
+            &server.url,
+        ]);
+        let client = GraphQlClient::from_runtime(
+            &config.runtime,
+            vec![Credential {
+                source: "test".to_string(),
+                token: "token".to_string(),
+            }],
+        )
+        .unwrap();
+        let storage = Storage::from_config(&config).unwrap();
+        let repositories = [repository("acme/api"), repository("acme/missing")];
+
+        assert_eq!(backup_issues(&client, &storage, &repositories).await, 1);
+        assert_eq!(backup_issues(&client, &storage, &repositories).await, 0);
+
+        let written = std::fs::read(output.path().join("issues/acme/api.json")).unwrap();
+        let written = serde_json::from_slice::<Value>(&written).unwrap();
+        assert_eq!(written["issues"][0]["title"], "first of issues");
+        assert_eq!(written["issues"][0]["state"], "closed");
+        assert_eq!(
+            written["pull_requests"][0]["title"],
+            "first of pullRequests"
+        );
+        assert!(!output.path().join("issues/acme/missing.json").exists());
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use tracing::info;
+
+use crate::{api::types::Repository, config::MaintenanceTask, error::Result, git::subprocess};
+
+use super::report::MaintenanceReport;
+
+/// Runs `task` on a clone and measures its `.git` directory before and after.
+pub async fn maintain_clone(
+    repository: &Repository,
+    clone_dir: &Path,
+    task: MaintenanceTask,
+    timeout: Duration,
+) -> Result<MaintenanceReport> {
+    let git_dir = clone_dir.join(".git");
+    let bytes_before = git_dir_size(&git_dir).await?;
+    info!(repo = %repository.full_name, task = ?task, "running git maintenance");
+    subprocess::run_maintenance(clone_dir, task, timeout).await?;
+    let bytes_after = git_dir_size(&git_dir).await?;
+
+    info!(
+        repo = %repository.full_name,
+        bytes_before,
+        bytes_after,
+        "git maintenance finished",
+    );
+    Ok(MaintenanceReport {
+        task,
+        bytes_before,
+        bytes_after,
+    })
+}
+
+/// Whether maintenance last run at `last` is due again `interval_days` later.
+pub fn is_due(last: Option<DateTime<Utc>>, interval_days: u32, now: DateTime<Utc>) -> bool {
+    last.is_none_or(|last| now - last >= TimeDelta::days(i64::from(interval_days)))
+}
+
+async fn git_dir_size(git_dir: &Path) -> Result<u64> {
+    let git_dir = git_dir.to_path_buf();
+    Ok(
+        tokio::task::spawn_blocking(move || directory_size(&git_dir))
+            .await
+            .map_err(io::Error::other)??,
+    )
+}
+
+/// Total size of the files under `path`, not following symlinks.
+fn directory_size(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            total += directory_size(&entry.path())?;
+        } else {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::test_support::git;
+
+    #[test]
+    fn maintenance_is_due_after_interval() {
+        let at = |day| Utc.with_ymd_and_hms(2026, 3, day, 2, 0, 0).unwrap();
+
+        assert!(is_due(None, 7, at(1)));
+        assert!(!is_due(Some(at(1)), 7, at(7)));
+        assert!(is_due(Some(at(1)), 7, at(8)));
+        assert!(is_due(Some(at(1)), 0, at(1)));
+    }
+
+    #[tokio::test]
+    async fn repack_packs_loose_objects_and_reports_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "--quiet", "-b", "main"]);
+        for content in ["one", "two", "three"] {
+            fs::write(dir.path().join("file"), content).unwrap();
+            git(dir.path(), &["add", "file"]);
+            git(dir.path(), &["commit", "--quiet", "-m", content]);
+        }
+        let repository = Repository {
+            id: 1,
+            name: "app".to_string(),
+            full_name: "owner/app".to_string(),
+            archived: false,
+            language: None,
+            clone_url: String::new(),
+            ssh_url: String::new(),
+            size: 0,
+        };
+
+        let report = maintain_clone(
+            &repository,
+            dir.path(),
+            MaintenanceTask::Repack,
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.task, MaintenanceTask::Repack);
+        assert!(report.bytes_before > 0);
+        assert!(report.bytes_after > 0);
+        let objects = git(dir.path(), &["count-objects", "-v"]);
+        assert!(objects.lines().any(|line| line == "count: 0"), "{objects}");
+        assert!(objects.lines().any(|line| line == "packs: 1"), "{objects}");
+    }
+}
@@ -1,4 +1,14 @@
+pub mod archives;
+pub mod inventory;
+pub mod issues;
+pub mod maintenance;
+pub mod orphans;
+pub mod repair;
+pub mod report;
 pub mod repositories;
+pub mod reset;
+pub mod snapshots;
+pub mod submodules;
 
 use tracing::info;
 
@@ -0,0 +1,286 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{info, warn};
+
+use crate::{api::types::Repository, config::OrphanPolicy, error::Result};
+
+use super::{
+    inventory::{Inventory, OrphanAction, OrphanedRepository},
+    repositories::clone_dir,
+};
+
+const ARCHIVE_DIR: &str = "_archived";
+
+/// Compares the previous inventory with the current listing and applies the
+/// orphan policy to clones whose repository is gone upstream. A clone kept by
+/// `keep` is archived once a listed repository takes its name, so the new
+/// repository can be cloned there. Returns the orphan records to store in the
+/// new inventory.
+pub fn reconcile_orphans(
+    root: &Path,
+    policy: OrphanPolicy,
+    previous: &Inventory,
+    current: &[Repository],
+) -> Vec<OrphanedRepository> {
+    let current_ids = current.iter().map(|repo| repo.id).collect::<HashSet<_>>();
+    let taken = current
+        .iter()
+        .map(|repo| clone_dir(root, repo))
+        .collect::<HashSet<_>>();
+    let mut orphaned = Vec::with_capacity(previous.orphaned.len());
+
+    for record in &previous.orphaned {
+        if current_ids.contains(&record.id) {
+            info!(repo = %record.full_name, "orphaned repository reappeared upstream");
+            continue;
+        }
+
+        let kept_path = match (&record.action, &record.path) {
+            (OrphanAction::Kept, Some(path)) => root.join(path),
+            _ => {
+                orphaned.push(record.clone());
+                continue;
+            }
+        };
+
+        let policy = policy_for(&kept_path, policy, &taken);
+        if policy == OrphanPolicy::Keep || !kept_path.exists() {
+            orphaned.push(record.clone());
+            continue;
+        }
+
+        let mut record = record.clone();
+        match apply_policy(root, &kept_path, policy) {
+            Ok((action, path)) => {
+                record.action = action;
+                record.path = path;
+            }
+            Err(error) => {
+                warn!(repo = %record.full_name, error = %error, "failed applying orphan policy");
+            }
+        }
+        orphaned.push(record);
+    }
+
+    let recorded_ids = orphaned
+        .iter()
+        .map(|record| record.id)
+        .collect::<HashSet<_>>();
+    let detected_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    for repository in &previous.repositories {
+        if current_ids.contains(&repository.id) || recorded_ids.contains(&repository.id) {
+            continue;
+        }
+
+        let path = clone_dir(root, repository);
+        if !path.exists() {
+            continue;
+        }
+
+        warn!(
+            repo = %repository.full_name,
+            path = %path.display(),
+            policy = ?policy,
+            "repository no longer listed upstream",
+        );
+
+        let policy = policy_for(&path, policy, &taken);
+        let (action, path) = match apply_policy(root, &path, policy) {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                warn!(repo = %repository.full_name, error = %error, "failed applying orphan policy");
+                (OrphanAction::Kept, relative_path(root, &path))
+            }
+        };
+
+        orphaned.push(OrphanedRepository {
+            id: repository.id,
+            full_name: repository.full_name.clone(),
+            detected_at: detected_at.clone(),
+            action,
+            path,
+        });
+    }
+
+    orphaned
+}
+
+/// The policy applied to the orphaned clone at `path`: `keep` turns into
+/// `archive` when a listed repository is cloned to the same path.
+fn policy_for(path: &Path, policy: OrphanPolicy, taken: &HashSet<PathBuf>) -> OrphanPolicy {
+    if policy == OrphanPolicy::Keep && taken.contains(path) {
+        info!(path = %path.display(), "name of kept orphan reused upstream, archiving it");
+        return OrphanPolicy::Archive;
+    }
+    policy
+}
+
+fn apply_policy(
+    root: &Path,
+    path: &Path,
+    policy: OrphanPolicy,
+) -> Result<(OrphanAction, Option<String>)> {
+    match policy {
+        OrphanPolicy::Keep => Ok((OrphanAction::Kept, relative_path(root, path))),
+        OrphanPolicy::Archive => {
+            let destination = archive_destination(root, path);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(path, &destination)?;
+            info!(from = %path.display(), to = %destination.display(), "archived orphaned clone");
+            Ok((OrphanAction::Archived, relative_path(root, &destination)))
+        }
+        OrphanPolicy::Delete => {
+            fs::remove_dir_all(path)?;
+            info!(path = %path.display(), "deleted orphaned clone");
+            Ok((OrphanAction::Deleted, None))
+        }
+    }
+}
+
+fn archive_destination(root: &Path, path: &Path) -> PathBuf {
+    let date = Utc::now().format("%Y-%m-%d").to_string();
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    root.join(ARCHIVE_DIR).join(date).join(relative)
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(root)
+        .ok()
+        .map(|relative| relative.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository(id: u64, full_name: &str) -> Repository {
+        Repository {
+            id,
+            name: full_name.split_once('/').unwrap().1.to_string(),
+            full_name: full_name.to_string(),
+            archived: false,
+            language: None,
+            clone_url: format!("https://github.com/{full_name}.git"),
+            ssh_url: format!("git@github.com:{full_name}.git"),
+            size: 0,
+        }
+    }
+
+    /// Previous run listed `octocat/old`, renamed to `octocat/new` since but
+    /// still matched by id, and `octocat/gone`, deleted upstream since. Both
+    /// clones are still under their old names.
+    fn setup() -> (tempfile::TempDir, Inventory, Vec<Repository>) {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["octocat/old", "octocat/gone"] {
+            fs::create_dir_all(dir.path().join(name)).unwrap();
+        }
+        let previous = Inventory {
+            repositories: vec![repository(1, "octocat/old"), repository(2, "octocat/gone")],
+            ..Inventory::default()
+        };
+        (dir, previous, vec![repository(1, "octocat/new")])
+    }
+
+    #[test]
+    fn keep_records_orphans_and_leaves_clones() {
+        let (dir, previous, current) = setup();
+
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Keep, &previous, &current);
+
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].id, 2);
+        assert_eq!(orphaned[0].action, OrphanAction::Kept);
+        assert_eq!(orphaned[0].path.as_deref(), Some("octocat/gone"));
+        assert!(dir.path().join("octocat/gone").exists());
+        assert!(dir.path().join("octocat/old").exists());
+    }
+
+    #[test]
+    fn archive_moves_orphans_below_archive_dir() {
+        let (dir, previous, current) = setup();
+
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Archive, &previous, &current);
+
+        let expected = archive_destination(dir.path(), &dir.path().join("octocat/gone"));
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].action, OrphanAction::Archived);
+        assert_eq!(orphaned[0].path, relative_path(dir.path(), &expected));
+        assert!(expected.exists());
+        assert!(!dir.path().join("octocat/gone").exists());
+        assert!(dir.path().join("octocat/old").exists());
+    }
+
+    #[test]
+    fn delete_removes_orphans() {
+        let (dir, previous, current) = setup();
+
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Delete, &previous, &current);
+
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].action, OrphanAction::Deleted);
+        assert_eq!(orphaned[0].path, None);
+        assert!(!dir.path().join("octocat/gone").exists());
+        assert!(dir.path().join("octocat/old").exists());
+    }
+
+    #[test]
+    fn applies_new_policy_to_kept_orphans_and_drops_reappeared_ones() {
+        let (dir, previous, current) = setup();
+        let kept = reconcile_orphans(dir.path(), OrphanPolicy::Keep, &previous, &current);
+        let previous = Inventory {
+            repositories: current.clone(),
+            orphaned: kept,
+            ..Inventory::default()
+        };
+
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Delete, &previous, &current);
+        assert_eq!(orphaned[0].action, OrphanAction::Deleted);
+        assert!(!dir.path().join("octocat/gone").exists());
+
+        let previous = Inventory {
+            orphaned,
+            ..previous
+        };
+        let current = vec![repository(1, "octocat/new"), repository(2, "octocat/back")];
+        assert!(
+            reconcile_orphans(dir.path(), OrphanPolicy::Delete, &previous, &current).is_empty()
+        );
+    }
+
+    #[test]
+    fn keep_archives_orphans_whose_name_is_reused() {
+        let (dir, previous, current) = setup();
+        let kept = reconcile_orphans(dir.path(), OrphanPolicy::Keep, &previous, &current);
+        let previous = Inventory {
+            repositories: current.clone(),
+            orphaned: kept,
+            ..Inventory::default()
+        };
+
+        // `octocat/gone` is recreated upstream with a new id.
+        let current = vec![repository(1, "octocat/new"), repository(3, "octocat/gone")];
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Keep, &previous, &current);
+
+        let expected = archive_destination(dir.path(), &dir.path().join("octocat/gone"));
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].id, 2);
+        assert_eq!(orphaned[0].action, OrphanAction::Archived);
+        assert_eq!(orphaned[0].path, relative_path(dir.path(), &expected));
+        assert!(expected.exists());
+        assert!(!dir.path().join("octocat/gone").exists());
+
+        // Also when the deletion and the new repository are seen by the same run.
+        let (dir, previous, _) = setup();
+        let orphaned = reconcile_orphans(dir.path(), OrphanPolicy::Keep, &previous, &current);
+        assert_eq!(orphaned[0].action, OrphanAction::Archived);
+        assert!(!dir.path().join("octocat/gone").exists());
+    }
+}
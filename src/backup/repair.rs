@@ -0,0 +1,186 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use tracing::{info, warn};
+
+use crate::{error::Result, git::backend::GitBackend};
+
+const QUARANTINE_DIR: &str = "_quarantine";
+const QUARANTINE_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Checks that `clone_dir` holds a usable clone. Returns why it is broken,
+/// or `None` when it looks intact.
+///
+/// Catches directories left by an interrupted clone or corrupted by a full
+/// disk: a missing `.git` or `HEAD`, a repository `backend` refuses to open,
+/// and a `HEAD` commit whose objects are missing. Clones of empty repositories
+/// have no branches and are accepted. Failures that do not point at the clone,
+/// such as a permission error or a missing `git` executable, are returned.
+pub async fn check_clone(backend: &dyn GitBackend, clone_dir: &Path) -> Result<Option<String>> {
+    let git_dir = clone_dir.join(".git");
+    if !git_dir.is_dir() {
+        return Ok(Some("not a git repository, .git is missing".to_string()));
+    }
+    if !git_dir.join("HEAD").is_file() {
+        return Ok(Some("HEAD is missing".to_string()));
+    }
+
+    Ok(backend.check_clone(clone_dir).await?)
+}
+
+/// Moves a broken clone to `_quarantine/<timestamp>/owner/repo` under `root`,
+/// keeping it for inspection instead of deleting it. Returns the new path.
+pub fn quarantine_clone(root: &Path, clone_dir: &Path, now: DateTime<Utc>) -> Result<PathBuf> {
+    let relative = clone_dir.strip_prefix(root).unwrap_or(clone_dir);
+    let destination = root
+        .join(QUARANTINE_DIR)
+        .join(now.format(QUARANTINE_TIMESTAMP_FORMAT).to_string())
+        .join(relative);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::rename(clone_dir, &destination)?;
+    warn!(
+        from = %clone_dir.display(),
+        to = %destination.display(),
+        "moved broken clone to quarantine",
+    );
+    Ok(destination)
+}
+
+/// Removes quarantined clones moved more than `retention_days` before `now`;
+/// `0` keeps them forever. Returns the removed `_quarantine/<timestamp>`
+/// directories.
+pub fn prune_quarantine(
+    root: &Path,
+    retention_days: u32,
+    now: DateTime<Utc>,
+) -> io::Result<Vec<PathBuf>> {
+    let quarantine = root.join(QUARANTINE_DIR);
+    if retention_days == 0 || !quarantine.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let cutoff = now - TimeDelta::days(i64::from(retention_days));
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(&quarantine)? {
+        let path = entry?.path();
+        let moved_at = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| NaiveDateTime::parse_from_str(name, QUARANTINE_TIMESTAMP_FORMAT).ok());
+        // Directories not created by `quarantine_clone` are left alone.
+        if moved_at.is_some_and(|moved_at| moved_at.and_utc() < cutoff) {
+            fs::remove_dir_all(&path)?;
+            info!(path = %path.display(), "removed expired quarantined clones");
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{git::backend::SubprocessBackend, test_support::git};
+
+    fn repository(dir: &Path) -> PathBuf {
+        let clone = dir.join("owner").join("repo");
+        fs::create_dir_all(&clone).unwrap();
+        git(&clone, &["init", "--quiet"]);
+        fs::write(clone.join("README"), "hello").unwrap();
+        git(&clone, &["add", "README"]);
+        git(&clone, &["commit", "--quiet", "-m", "initial"]);
+        clone
+    }
+
+    fn object_path(clone: &Path, oid: &str) -> PathBuf {
+        clone.join(".git/objects").join(&oid[..2]).join(&oid[2..])
+    }
+
+    #[tokio::test]
+    async fn accepts_intact_and_empty_clones() {
+        let dir = tempfile::tempdir().unwrap();
+        let clone = repository(dir.path());
+        assert_eq!(check_clone(&SubprocessBackend, &clone).await.unwrap(), None);
+
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        git(&empty, &["init", "--quiet"]);
+        assert_eq!(check_clone(&SubprocessBackend, &empty).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn detects_broken_clones() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let partial = dir.path().join("partial");
+        fs::create_dir(&partial).unwrap();
+        assert!(check_clone(&SubprocessBackend, &partial)
+            .await
+            .unwrap()
+            .is_some());
+
+        let clone = repository(dir.path());
+        let commit = git(&clone, &["rev-parse", "HEAD"]);
+        let tree = git(&clone, &["rev-parse", "HEAD^{tree}"]);
+
+        fs::remove_file(object_path(&clone, &tree)).unwrap();
+        fs::remove_file(object_path(&clone, &commit)).unwrap();
+        let reason = check_clone(&SubprocessBackend, &clone)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reason.starts_with("HEAD does not resolve"), "{reason}");
+
+        fs::write(object_path(&clone, &commit), "garbage").unwrap();
+        let reason = check_clone(&SubprocessBackend, &clone)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reason.contains("corrupt"), "{reason}");
+
+        fs::write(clone.join(".git/HEAD"), "garbage").unwrap();
+        let reason = check_clone(&SubprocessBackend, &clone)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reason.contains("not a git repository"), "{reason}");
+    }
+
+    #[test]
+    fn quarantines_and_prunes_expired_clones() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let at = |day| Utc.with_ymd_and_hms(2026, 3, day, 2, 0, 0).unwrap();
+
+        let clone = repository(root);
+        let quarantined = quarantine_clone(root, &clone, at(1)).unwrap();
+        assert_eq!(
+            quarantined,
+            root.join("_quarantine/20260301T020000Z/owner/repo")
+        );
+        assert!(quarantined.join("README").is_file());
+        assert!(!clone.exists());
+
+        let clone = repository(root);
+        quarantine_clone(root, &clone, at(20)).unwrap();
+        fs::create_dir_all(root.join("_quarantine/notes")).unwrap();
+
+        assert!(prune_quarantine(root, 0, at(28)).unwrap().is_empty());
+        assert_eq!(
+            prune_quarantine(root, 14, at(28)).unwrap(),
+            vec![root.join("_quarantine/20260301T020000Z")]
+        );
+        assert!(root
+            .join("_quarantine/20260320T020000Z/owner/repo")
+            .is_dir());
+        assert!(root.join("_quarantine/notes").is_dir());
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    api::token_pool::TokenStats, config::MaintenanceTask, error::Result,
+    git::progress::TransferStats, storage::Storage,
+};
+
+pub const REPORT_KEY: &str = "backup-report.json";
+
+/// Summary of one backup run, written next to the inventory.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub repositories: Vec<RepositoryReport>,
+    pub tokens: Vec<TokenStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositoryReport {
+    pub full_name: String,
+    pub outcome: SyncOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repair: Option<RepairReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<TransferStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<MaintenanceReport>,
+}
+
+/// A broken clone that was moved aside and cloned again.
+#[derive(Debug, Clone, Serialize)]
+pub struct RepairReport {
+    pub reason: String,
+    /// Quarantine directory relative to `repositories/`.
+    pub quarantined_to: String,
+}
+
+/// Maintenance run on a clone, with the size of its `.git` directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub task: MaintenanceTask,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Cloned,
+    Updated,
+    /// Already synced by the interrupted run being resumed.
+    Skipped,
+    /// Larger than `--max-repo-size`, left untouched.
+    TooLarge,
+    Failed,
+}
+
+impl RunReport {
+    pub fn new(started_at: DateTime<Utc>) -> Self {
+        Self {
+            started_at,
+            finished_at: None,
+            repositories: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    fn count(&self, outcome: SyncOutcome) -> usize {
+        self.repositories
+            .iter()
+            .filter(|repository| repository.outcome == outcome)
+            .count()
+    }
+
+    pub async fn write(mut self, storage: &Storage) -> Result<()> {
+        self.finished_at = Some(Utc::now());
+        self.repositories
+            .sort_by(|left, right| left.full_name.cmp(&right.full_name));
+
+        let mut transfer = TransferStats::default();
+        for stats in self
+            .repositories
+            .iter()
+            .filter_map(|repository| repository.transfer)
+        {
+            transfer.add(stats);
+        }
+
+        info!(
+            cloned = self.count(SyncOutcome::Cloned),
+            updated = self.count(SyncOutcome::Updated),
+            skipped = self.count(SyncOutcome::Skipped),
+            too_large = self.count(SyncOutcome::TooLarge),
+            failed = self.count(SyncOutcome::Failed),
+            repaired = self
+                .repositories
+                .iter()
+                .filter(|repository| repository.repair.is_some())
+                .count(),
+            maintained = self
+                .repositories
+                .iter()
+                .filter(|repository| repository.maintenance.is_some())
+                .count(),
+            reclaimed_bytes = self
+                .repositories
+                .iter()
+                .filter_map(|repository| repository.maintenance.as_ref())
+                .map(|maintenance| maintenance.bytes_before.saturating_sub(maintenance.bytes_after))
+                .sum::<u64>(),
+            objects = transfer.objects,
+            bytes = transfer.bytes,
+            path = %storage.describe(REPORT_KEY),
+            "backup run finished",
+        );
+        for token in &self.tokens {
+            info!(
+                token = %token.source,
+                requests = token.requests,
+                rate_limited = token.rate_limited,
+                remaining = %token
+                    .rate_limits
+                    .iter()
+                    .map(|limit| format!("{}={}", limit.resource, limit.remaining))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                "token usage",
+            );
+        }
+        storage.write_json_if_changed(REPORT_KEY, &self).await?;
+        Ok(())
+    }
+}
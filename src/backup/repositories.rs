@@ -1,68 +1,407 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
+use chrono::{DateTime, Utc};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
 use reqwest::StatusCode;
 use serde::Deserialize;
-use serde_json::Value;
+use tokio::{sync::Semaphore, task::JoinSet};
 use tracing::{info, warn};
 
 use crate::{
-    api::{client::GitHubClient, types::Repository},
-    auth,
-    config::{BackupConfig, BackupScope},
+    api::{
+        cache,
+        client::{extract_legal_url, GitHubClient},
+        graphql::{GraphQlClient, RepositoryOwner},
+        types::Repository,
+    },
+    auth::{self, preflight},
+    config::{BackupConfig, BackupScope, CloneMode, UpdateStrategy},
     error::{ApiError, BackupError, Result},
-    git::subprocess,
-    io::smart_write::write_json_if_changed,
+    git::{self, backend::GitBackend, progress::TransferStats, subprocess},
+    incremental::state::{BackupState, RepositoryState},
+    io::disk,
+    shutdown,
+    storage::Storage,
+};
+
+use super::{
+    archives,
+    inventory::{
+        load_inventory, remove_unencrypted_inventory, Inventory, OrphanAction, INVENTORY_KEY,
+    },
+    issues, maintenance,
+    orphans::reconcile_orphans,
+    repair,
+    report::{MaintenanceReport, RepairReport, RepositoryReport, RunReport, SyncOutcome},
+    reset, snapshots, submodules,
 };
 
+/// Directory under `repositories/` where clones are written before they are
+/// moved into place.
+pub(crate) const PARTIAL_DIR: &str = ".partial";
+
 pub async fn backup_repositories(config: &BackupConfig) -> Result<()> {
     info!("retrieving repositories");
 
-    let token = auth::resolve_token(&config.auth)?;
-    let client = GitHubClient::from_runtime(&config.runtime, token)?;
-    let repositories = retrieve_repositories(config, &client).await?;
+    let mut report = RunReport::new(Utc::now());
+    let credentials = auth::resolve_credentials(&config.auth).await?;
+    if config.auth.preflight {
+        preflight::run_preflight(config, &credentials).await?;
+    }
+    let storage = Storage::from_config(config)?;
+    let root = config.output_dir.join("repositories");
+    let state_path = config.output_dir.join("state.json");
+    let mut state = BackupState::load(&state_path)?;
+
+    let partial_root = root.join(PARTIAL_DIR);
+    if partial_root.exists() {
+        info!(path = %partial_root.display(), "removing clones left by an interrupted run");
+        fs::remove_dir_all(&partial_root)?;
+    }
+    let (quarantine_root, retention_days) = (root.clone(), config.quarantine_retention_days);
+    tokio::task::spawn_blocking(move || {
+        repair::prune_quarantine(&quarantine_root, retention_days, Utc::now())
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    if let Some(dir) = &config.runtime.http_cache_dir {
+        let encrypted = config.runtime.http_cache_key_file.is_some();
+        match cache::prune(dir, encrypted, SystemTime::now()) {
+            Ok(0) => {}
+            Ok(removed) => info!(removed, "removed unused http cache entries"),
+            Err(error) => warn!(error = %error, "failed pruning the http cache"),
+        }
+    }
+    let resume_since = state.resume_since;
+    if let Some(since) = resume_since {
+        info!(since = %since, "resuming interrupted run, skipping repositories synced since");
+    }
+
+    let rest_client;
+    let mut graphql_client = None;
+    let (mut listing, tokens) =
+        if config.runtime.use_graphql && !matches!(config.scope, BackupScope::Repositories(_)) {
+            let client = GraphQlClient::from_runtime(&config.runtime, credentials)?;
+            let repositories = retrieve_repositories_graphql(config, &client).await?;
+            let tokens = client.tokens().clone();
+            graphql_client = Some(client);
+            (
+                stream::iter(repositories.into_iter().map(Ok)).boxed(),
+                tokens,
+            )
+        } else {
+            rest_client = GitHubClient::from_runtime(&config.runtime, credentials)?;
+            (
+                retrieve_repositories(config, &rest_client).await?,
+                rest_client.tokens().clone(),
+            )
+        };
+
+    // Issues are always fetched over GraphQL, sharing the listing's tokens.
+    let issues_client = match (config.backup_issues, graphql_client) {
+        (false, _) => None,
+        (true, Some(client)) => Some(client),
+        (true, None) => Some(GraphQlClient::with_tokens(&config.runtime, tokens.clone())?),
+    };
+
+    // Clones start as soon as a repository is listed, while later pages are
+    // still being fetched.
+    let mut clones = CloneScheduler::new(config, &storage, &root);
+    let mut space = SpaceBudget::new(&config.output_dir)?;
+    let mut repositories = Vec::new();
+    // Repositories whose directory holds the clone of another repository id,
+    // synced once orphans are reconciled.
+    let mut deferred = Vec::new();
+    let listed = loop {
+        let next = tokio::select! {
+            next = listing.try_next() => next,
+            () = shutdown::cancelled() => break Err(BackupError::Interrupted),
+        };
+        match next {
+            Ok(Some(repository)) => {
+                let previous = state.repositories.get(&repository.id);
+                let size = repository.size.saturating_mul(1024);
+                let skipped = if synced_since(&root, previous, resume_since) {
+                    Some(SyncOutcome::Skipped)
+                } else if config.max_repo_size.is_some_and(|max| size > max) {
+                    info!(repo = %repository.full_name, size, "repository exceeds --max-repo-size, skipping");
+                    Some(SyncOutcome::TooLarge)
+                } else {
+                    None
+                };
+                if let Some(outcome) = skipped {
+                    report.repositories.push(RepositoryReport {
+                        full_name: repository.full_name.clone(),
+                        outcome,
+                        error: None,
+                        repair: None,
+                        transfer: None,
+                        maintenance: None,
+                    });
+                } else if claimed_by_other(&state, &root, &repository).is_some() {
+                    deferred.push(repository.clone());
+                } else {
+                    if !has_clone(&state, &root, &repository) {
+                        if let Err(error) = space.reserve(size) {
+                            break Err(error);
+                        }
+                    }
+                    clones.schedule(&repository, previous);
+                }
+                repositories.push(repository);
+            }
+            Ok(None) => break Ok(()),
+            Err(error) => break Err(error),
+        }
+    };
+    drop(listing);
+
+    if let Err(error) = listed {
+        // Keep the progress of clones that already started before giving up.
+        // An incomplete listing must not update the inventory, or repositories
+        // that were not listed yet would be treated as deleted upstream.
+        clones.finish(&mut state, &mut report).await;
+        let _ = fs::remove_dir_all(&partial_root);
+        state.resume_since = Some(resume_since.unwrap_or(report.started_at));
+        state.save(&state_path)?;
+        report.tokens = tokens.stats();
+        report.write(&storage).await?;
+        return Err(error);
+    }
 
     if repositories.is_empty() {
         info!("no repositories found for this backup target");
-        return Ok(());
+        state.resume_since = None;
+        state.save(&state_path)?;
+        report.tokens = tokens.stats();
+        return report.write(&storage).await;
+    }
+
+    // Orphaned clones are moved or deleted only once no sync is writing to
+    // the repositories directory.
+    clones.finish(&mut state, &mut report).await;
+    let previous = load_inventory(&storage).await?;
+    let orphaned = match config.scope {
+        BackupScope::User(_) | BackupScope::Organization(_) => {
+            let (root, policy, current) =
+                (root.clone(), config.orphan_policy, repositories.clone());
+            tokio::task::spawn_blocking(move || {
+                reconcile_orphans(&root, policy, &previous, &current)
+            })
+            .await
+            .map_err(std::io::Error::other)?
+        }
+        BackupScope::Repositories(_) | BackupScope::Unknown => previous.orphaned,
+    };
+    for record in &orphaned {
+        if record.action != OrphanAction::Kept {
+            state.repositories.remove(&record.id);
+        }
+    }
+
+    let mut deferred_clones = CloneScheduler::new(config, &storage, &root);
+    for repository in deferred {
+        let ready = match claimed_by_other(&state, &root, &repository) {
+            Some(owner) => Err(BackupError::CloneDirectoryInUse {
+                path: clone_dir(&root, &repository).display().to_string(),
+                owner,
+            }),
+            None if has_clone(&state, &root, &repository) => Ok(()),
+            None => space.reserve(repository.size.saturating_mul(1024)),
+        };
+        match ready {
+            Ok(()) => deferred_clones.schedule(&repository, state.repositories.get(&repository.id)),
+            Err(error) => {
+                warn!(repo = %repository.full_name, error = %error, "repository sync step failed, continuing");
+                report.repositories.push(RepositoryReport {
+                    full_name: repository.full_name.clone(),
+                    outcome: SyncOutcome::Failed,
+                    error: Some(error.to_string()),
+                    repair: None,
+                    transfer: None,
+                    maintenance: None,
+                });
+            }
+        }
+    }
+    deferred_clones.finish(&mut state, &mut report).await;
+
+    let submodules = if config.backup_submodules && !shutdown::is_requested() {
+        submodules::backup_submodules(config, &root, &repositories, &mut report).await
+    } else {
+        Vec::new()
+    };
+    let _ = fs::remove_dir_all(&partial_root);
+
+    if let Some(client) = &issues_client {
+        issues::backup_issues(client, &storage, &repositories).await;
     }
 
-    let inventory_path = config.output_dir.join("repositories.json");
-    let wrote_inventory = write_json_if_changed(&inventory_path, &repositories)?;
-    if wrote_inventory {
+    let inventory = Inventory {
+        repositories,
+        orphaned,
+        submodules,
+    };
+    let inventory_location = storage.describe(INVENTORY_KEY);
+    if inventory.write(&storage).await? {
         info!(
-            path = %inventory_path.display(),
-            count = repositories.len(),
+            path = %inventory_location,
+            count = inventory.repositories.len(),
+            orphaned = inventory.orphaned.len(),
             "wrote repository inventory",
         );
+        remove_unencrypted_inventory(&storage).await?;
     } else {
         info!(
-            path = %inventory_path.display(),
-            count = repositories.len(),
+            path = %inventory_location,
+            count = inventory.repositories.len(),
+            orphaned = inventory.orphaned.len(),
             "repository inventory unchanged",
         );
     }
 
-    backup_git_clones(config, &repositories)
+    // Clones cancelled by a shutdown are retried by the next run, which
+    // skips the repositories this run already finished.
+    let interrupted = shutdown::is_requested();
+    state.resume_since = interrupted.then(|| resume_since.unwrap_or(report.started_at));
+    state.save(&state_path)?;
+    report.tokens = tokens.stats();
+    report.write(&storage).await?;
+
+    if interrupted {
+        return Err(BackupError::Interrupted);
+    }
+    Ok(())
 }
 
-async fn retrieve_repositories(
-    config: &BackupConfig,
-    client: &GitHubClient,
-) -> Result<Vec<Repository>> {
+/// Whether an existing clone will be updated rather than a new one cloned.
+/// A directory recorded for another repository id is never reused.
+fn has_clone(state: &BackupState, root: &Path, repository: &Repository) -> bool {
+    let recorded = state
+        .repositories
+        .get(&repository.id)
+        .is_some_and(|entry| root.join(&entry.path).exists());
+    recorded
+        || (clone_dir(root, repository).exists()
+            && claimed_by_other(state, root, repository).is_none())
+}
+
+/// Id of another repository whose clone is recorded in `repository`'s
+/// directory, e.g. after a transfer or when a deleted repository was
+/// recreated under the same name.
+fn claimed_by_other(state: &BackupState, root: &Path, repository: &Repository) -> Option<u64> {
+    let dir = clone_dir(root, repository);
+    state
+        .repositories
+        .iter()
+        .find(|(id, entry)| **id != repository.id && root.join(&entry.path) == dir)
+        .map(|(id, _)| *id)
+}
+
+/// Free space of the output filesystem at the start of the run, against
+/// which the sizes GitHub reports for new clones are reserved.
+struct SpaceBudget {
+    path: PathBuf,
+    available: Option<u64>,
+    projected: u64,
+}
+
+impl SpaceBudget {
+    fn new(output_dir: &Path) -> Result<Self> {
+        let available = disk::available_space(output_dir)?;
+        if let Some(available) = available {
+            info!(path = %output_dir.display(), available, "free space on output filesystem");
+        }
+        Ok(Self {
+            path: output_dir.to_path_buf(),
+            available,
+            projected: 0,
+        })
+    }
+
+    /// Adds a new clone of `size` bytes, failing when the clones scheduled
+    /// so far would not fit.
+    fn reserve(&mut self, size: u64) -> Result<()> {
+        let projected = self.projected.saturating_add(size);
+        match self.available {
+            Some(available) if projected > available => Err(BackupError::InsufficientSpace {
+                path: self.path.display().to_string(),
+                required: projected,
+                available,
+            }),
+            _ => {
+                self.projected = projected;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Whether the repository was synced by the interrupted run that started at
+/// `since` and its clone is still in place.
+fn synced_since(
+    root: &Path,
+    previous: Option<&RepositoryState>,
+    since: Option<DateTime<Utc>>,
+) -> bool {
+    match (previous, since) {
+        (Some(entry), Some(since)) => {
+            entry.synced_at.is_some_and(|synced_at| synced_at >= since)
+                && root.join(&entry.path).exists()
+        }
+        _ => false,
+    }
+}
+
+async fn retrieve_repositories<'a>(
+    config: &'a BackupConfig,
+    client: &'a GitHubClient,
+) -> Result<BoxStream<'a, Result<Repository>>> {
     match &config.scope {
-        BackupScope::User(user) => retrieve_user_repositories(client, user).await,
-        BackupScope::Organization(org) => client
-            .get_paginated(&format!("/orgs/{org}/repos?per_page=100&type=all"))
+        BackupScope::User(user) => Ok(retrieve_user_repositories(client, user)
             .await
-            .map_err(Into::into),
+            .map_err(Into::into)
+            .boxed()),
+        BackupScope::Organization(org) => Ok(client
+            .paginate(&format!("/orgs/{org}/repos?per_page=100&type=all"))
+            .map_err(Into::into)
+            .boxed()),
         BackupScope::Repositories(repositories) => {
-            retrieve_selected_repositories(client, repositories).await
+            let repositories = retrieve_selected_repositories(client, repositories).await?;
+            Ok(stream::iter(repositories.into_iter().map(Ok)).boxed())
         }
-        BackupScope::Unknown => Ok(Vec::new()),
+        BackupScope::Unknown => Ok(stream::empty().boxed()),
     }
 }
 
-async fn retrieve_user_repositories(client: &GitHubClient, user: &str) -> Result<Vec<Repository>> {
+async fn retrieve_repositories_graphql(
+    config: &BackupConfig,
+    client: &GraphQlClient,
+) -> Result<Vec<Repository>> {
+    let owner = match &config.scope {
+        BackupScope::User(user) => match client.viewer_login().await {
+            Ok(login) if login.eq_ignore_ascii_case(user) => RepositoryOwner::Viewer,
+            _ => RepositoryOwner::User(user.clone()),
+        },
+        BackupScope::Organization(org) => RepositoryOwner::Organization(org.clone()),
+        BackupScope::Repositories(_) | BackupScope::Unknown => return Ok(Vec::new()),
+    };
+
+    client.list_repositories(&owner).await.map_err(Into::into)
+}
+
+async fn retrieve_user_repositories<'a>(
+    client: &'a GitHubClient,
+    user: &str,
+) -> impl Stream<Item = std::result::Result<Repository, ApiError>> + 'a {
     let list_path = match client.get_json::<AuthenticatedUser>("/user").await {
         Ok(authenticated) if authenticated.login.eq_ignore_ascii_case(user) => {
             "/user/repos?per_page=100&type=all&sort=full_name".to_string()
@@ -70,7 +409,7 @@ async fn retrieve_user_repositories(client: &GitHubClient, user: &str) -> Result
         _ => format!("/users/{user}/repos?per_page=100&type=all&sort=full_name"),
     };
 
-    client.get_paginated(&list_path).await.map_err(Into::into)
+    client.paginate(&list_path)
 }
 
 async fn retrieve_selected_repositories(
@@ -119,48 +458,344 @@ fn normalize_repo_path(input: &str) -> Result<String> {
     )))
 }
 
-fn extract_legal_url(message: &str) -> Option<String> {
-    let value = serde_json::from_str::<Value>(message).ok()?;
-    value
-        .get("block")
-        .and_then(|block| block.get("html_url"))
-        .and_then(Value::as_str)
-        .map(ToString::to_string)
+/// Runs repository syncs in the background, at most `concurrency` at a time.
+struct CloneScheduler {
+    config: Arc<BackupConfig>,
+    storage: Storage,
+    root: PathBuf,
+    limit: Arc<Semaphore>,
+    tasks: JoinSet<RepositoryOutcome>,
 }
 
-fn backup_git_clones(config: &BackupConfig, repositories: &[Repository]) -> Result<()> {
-    let root = config.output_dir.join("repositories");
-    fs::create_dir_all(&root)?;
+impl CloneScheduler {
+    fn new(config: &BackupConfig, storage: &Storage, root: &Path) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            storage: storage.clone(),
+            root: root.to_path_buf(),
+            limit: Arc::new(Semaphore::new(config.runtime.concurrency)),
+            tasks: JoinSet::new(),
+        }
+    }
 
-    for repository in repositories {
-        if let Err(error) = backup_single_repository(&root, repository) {
+    fn schedule(&mut self, repository: &Repository, previous: Option<&RepositoryState>) {
+        let config = Arc::clone(&self.config);
+        let storage = self.storage.clone();
+        let root = self.root.clone();
+        let limit = Arc::clone(&self.limit);
+        let repository = repository.clone();
+        let previous = previous.cloned();
+
+        self.tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await.ok();
+            sync_repository(&config, &storage, &root, &repository, previous).await
+        });
+    }
+
+    /// Waits for every scheduled sync, recording the clones that exist in
+    /// `state` and every outcome in `report`.
+    async fn finish(mut self, state: &mut BackupState, report: &mut RunReport) {
+        while let Some(result) = self.tasks.join_next().await {
+            match result {
+                Ok(outcome) => {
+                    if let Some(entry) = outcome.entry {
+                        state.repositories.insert(outcome.id, entry);
+                    }
+                    report.repositories.push(outcome.report);
+                }
+                Err(error) => warn!(error = %error, "repository sync task failed"),
+            }
+        }
+    }
+}
+
+struct RepositoryOutcome {
+    id: u64,
+    /// State entry, present when a clone exists after the sync.
+    entry: Option<RepositoryState>,
+    report: RepositoryReport,
+}
+
+async fn sync_repository(
+    config: &BackupConfig,
+    storage: &Storage,
+    root: &Path,
+    repository: &Repository,
+    previous: Option<RepositoryState>,
+) -> RepositoryOutcome {
+    let clone_dir = clone_dir(root, repository);
+    let previous_dir = previous.as_ref().map(|entry| root.join(&entry.path));
+    let mut entry = previous.unwrap_or_else(|| RepositoryState {
+        full_name: repository.full_name.clone(),
+        path: String::new(),
+        archive_refs_digest: None,
+        clone_mode: CloneMode::default(),
+        synced_at: None,
+        maintained_at: None,
+    });
+
+    let mut details = SyncDetails::default();
+    let result = backup_single_repository(
+        config,
+        storage,
+        root,
+        repository,
+        previous_dir.as_deref(),
+        &mut entry,
+        &mut details,
+    )
+    .await;
+    let report = match result {
+        Ok(outcome) => {
+            entry.synced_at = Some(Utc::now());
+            RepositoryReport {
+                full_name: repository.full_name.clone(),
+                outcome,
+                error: None,
+                repair: details.repair,
+                transfer: details.transfer,
+                maintenance: details.maintenance,
+            }
+        }
+        Err(error) => {
             warn!(
                 repo = %repository.full_name,
                 error = %error,
                 "repository sync step failed, continuing",
             );
+            RepositoryReport {
+                full_name: repository.full_name.clone(),
+                outcome: SyncOutcome::Failed,
+                error: Some(error.to_string()),
+                repair: details.repair,
+                transfer: details.transfer,
+                maintenance: details.maintenance,
+            }
         }
-    }
+    };
 
-    Ok(())
+    let entry = clone_dir.exists().then(|| {
+        entry.full_name = repository.full_name.clone();
+        entry.path = clone_dir
+            .strip_prefix(root)
+            .unwrap_or(&clone_dir)
+            .to_string_lossy()
+            .into_owned();
+        entry
+    });
+
+    RepositoryOutcome {
+        id: repository.id,
+        entry,
+        report,
+    }
 }
 
-fn backup_single_repository(root: &Path, repository: &Repository) -> Result<()> {
+pub(crate) fn clone_dir(root: &Path, repository: &Repository) -> PathBuf {
     let (owner, repo_name) = repository
         .full_name
         .split_once('/')
         .unwrap_or(("unknown", repository.name.as_str()));
 
-    let clone_dir = root.join(owner).join(repo_name);
+    root.join(owner).join(repo_name)
+}
+
+/// Parts of a sync reported even when a later step fails.
+#[derive(Debug, Default)]
+struct SyncDetails {
+    repair: Option<RepairReport>,
+    transfer: Option<TransferStats>,
+    maintenance: Option<MaintenanceReport>,
+}
+
+fn log_transfer(repository: &Repository, transfer: &TransferStats) {
+    info!(
+        repo = %repository.full_name,
+        objects = transfer.objects,
+        bytes = transfer.bytes,
+        refs_updated = transfer.refs_updated,
+        "git transfer finished",
+    );
+}
+
+async fn backup_single_repository(
+    config: &BackupConfig,
+    storage: &Storage,
+    root: &Path,
+    repository: &Repository,
+    previous_dir: Option<&Path>,
+    entry: &mut RepositoryState,
+    details: &mut SyncDetails,
+) -> Result<SyncOutcome> {
+    let backend = git::backend::backend(config.runtime.git_backend)?;
+    let clone_dir = clone_dir(root, repository);
+    if let Some(previous_dir) = previous_dir {
+        if previous_dir != clone_dir && previous_dir.exists() && !clone_dir.exists() {
+            relocate_clone(backend, repository, previous_dir, &clone_dir).await?;
+        }
+    }
+
     if clone_dir.exists() {
+        if let Some(reason) = repair::check_clone(backend, &clone_dir).await? {
+            warn!(repo = %repository.full_name, reason = %reason, "clone is broken, cloning again");
+            let quarantined = repair::quarantine_clone(root, &clone_dir, Utc::now())?;
+            details.repair = Some(RepairReport {
+                reason,
+                quarantined_to: quarantined
+                    .strip_prefix(root)
+                    .unwrap_or(&quarantined)
+                    .to_string_lossy()
+                    .into_owned(),
+            });
+        }
+    }
+
+    let outcome = if clone_dir.exists() {
+        let fetched_from = match config.update_strategy {
+            UpdateStrategy::Reset => Some(reset::remote_tips(&clone_dir).await?),
+            UpdateStrategy::FastForward => None,
+        };
+        if config.snapshots.enabled {
+            if let Some(timestamp) = snapshots::create_snapshot(&clone_dir, Utc::now()).await? {
+                info!(repo = %repository.full_name, snapshot = %timestamp, "saved refs snapshot");
+            }
+        }
+
         info!(repo = %repository.full_name, path = %clone_dir.display(), "updating repository clone");
-        subprocess::update_repository(&clone_dir)?;
-    } else {
-        if let Some(parent) = clone_dir.parent() {
-            fs::create_dir_all(parent)?;
+        let unshallow = config.unshallow && !entry.clone_mode.is_full();
+        let transfer = backend
+            .update_repository(
+                &clone_dir,
+                &entry.clone_mode,
+                unshallow,
+                config.update_strategy,
+                Duration::from_secs(config.runtime.git_timeout_seconds),
+            )
+            .await?;
+        log_transfer(repository, &transfer);
+        details.transfer = Some(transfer);
+        if let Some(fetched_from) = &fetched_from {
+            reset::reset_to_remote(
+                &clone_dir,
+                fetched_from,
+                Utc::now(),
+                Duration::from_secs(config.runtime.git_timeout_seconds),
+            )
+            .await?;
+        }
+        if unshallow {
+            info!(repo = %repository.full_name, "fetched full history of shallow or partial clone");
+            entry.clone_mode = CloneMode::default();
         }
+
+        if config.snapshots.enabled {
+            snapshots::prune_snapshots(&clone_dir, &config.snapshots.retention).await?;
+        }
+        if let Some(task) = config.maintenance.task {
+            let now = Utc::now();
+            if maintenance::is_due(entry.maintained_at, config.maintenance.interval_days, now) {
+                details.maintenance = Some(
+                    maintenance::maintain_clone(
+                        repository,
+                        &clone_dir,
+                        task,
+                        Duration::from_secs(config.runtime.git_timeout_seconds),
+                    )
+                    .await?,
+                );
+                entry.maintained_at = Some(now);
+            }
+        }
+        SyncOutcome::Updated
+    } else {
         info!(repo = %repository.full_name, path = %clone_dir.display(), "cloning repository");
-        subprocess::clone_repository(&repository.clone_url, &clone_dir)?;
+        let transfer = clone_into_place(
+            backend,
+            &repository.clone_url,
+            &self::clone_dir(&root.join(PARTIAL_DIR), repository),
+            &clone_dir,
+            &config.clone_mode,
+            Duration::from_secs(config.runtime.git_timeout_seconds),
+        )
+        .await?;
+        entry.clone_mode = config.clone_mode.clone();
+        log_transfer(repository, &transfer);
+        details.transfer = Some(transfer);
+        SyncOutcome::Cloned
+    };
+
+    if config.submodules {
+        info!(repo = %repository.full_name, "updating submodules");
+        subprocess::update_submodules(
+            &clone_dir,
+            Duration::from_secs(config.runtime.git_timeout_seconds),
+        )
+        .await?;
+    }
+
+    if let Some(format) = config.archive_format {
+        archives::write_archive(config, storage, repository, &clone_dir, format, entry).await?;
+    }
+
+    Ok(outcome)
+}
+
+/// Clones into `partial_dir` and renames it to `clone_dir` on success, so an
+/// interrupted clone never looks like a complete one.
+pub(crate) async fn clone_into_place(
+    backend: &dyn GitBackend,
+    url: &str,
+    partial_dir: &Path,
+    clone_dir: &Path,
+    mode: &CloneMode,
+    timeout: Duration,
+) -> Result<TransferStats> {
+    for parent in [partial_dir.parent(), clone_dir.parent()]
+        .into_iter()
+        .flatten()
+    {
+        fs::create_dir_all(parent)?;
+    }
+
+    match backend
+        .clone_repository(url, partial_dir, mode, timeout)
+        .await
+    {
+        Ok(transfer) => {
+            fs::rename(partial_dir, clone_dir)?;
+            Ok(transfer)
+        }
+        Err(error) => {
+            let _ = fs::remove_dir_all(partial_dir);
+            Err(error.into())
+        }
+    }
+}
+
+/// Moves an existing clone after a rename or transfer instead of cloning the
+/// repository again under its new name.
+async fn relocate_clone(
+    backend: &dyn GitBackend,
+    repository: &Repository,
+    from: &Path,
+    to: &Path,
+) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    info!(
+        repo = %repository.full_name,
+        from = %from.display(),
+        to = %to.display(),
+        "repository renamed or transferred, moving existing clone",
+    );
+    fs::rename(from, to)?;
+    backend.set_remote_url(to, &repository.clone_url).await?;
+
+    if let Some(old_owner_dir) = from.parent() {
+        // Only succeeds when the previous owner directory is now empty.
+        let _ = fs::remove_dir(old_owner_dir);
     }
 
     Ok(())
@@ -170,3 +805,348 @@ fn backup_single_repository(root: &Path, repository: &Repository) -> Result<()>
 struct AuthenticatedUser {
     login: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        auth::Credential,
+        git::backend::SubprocessBackend,
+        test_support::{self, git, Response, TestServer},
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Repositories visible on `octocat`'s profile with the affiliation that
+    /// makes them visible.
+    const VISIBLE: [(u64, &str, &str); 3] = [
+        (1, "octocat/own", "OWNER"),
+        (2, "someone/shared", "COLLABORATOR"),
+        (3, "acme/team", "ORGANIZATION_MEMBER"),
+    ];
+
+    /// Stands in for both APIs: REST `type=all` returns every repository the
+    /// user owns or is a member of, GraphQL filters by `ownerAffiliations`.
+    fn github(request: &test_support::Request) -> Response {
+        if request.path == "/user" {
+            return Response::json(200, r#"{"login":"someone"}"#);
+        }
+        if request.path.starts_with("/users/octocat/repos?") && request.path.contains("type=all") {
+            let repositories = VISIBLE
+                .iter()
+                .map(|(id, full_name, _)| {
+                    json!({
+                        "id": id,
+                        "name": full_name.split_once('/').unwrap().1,
+                        "full_name": full_name,
+                        "archived": false,
+                        "language": null,
+                        "clone_url": format!("https://github.com/{full_name}.git"),
+                        "ssh_url": format!("git@github.com:{full_name}.git"),
+                    })
+                })
+                .collect::<Vec<_>>();
+            return Response::json(200, &Value::from(repositories).to_string());
+        }
+        if request.path != "/graphql" {
+            return Response::new(404);
+        }
+
+        let body = serde_json::from_slice::<Value>(&request.body).unwrap();
+        let query = body["query"].as_str().unwrap();
+        if query.contains("viewer { login }") {
+            return Response::json(200, r#"{"data":{"viewer":{"login":"someone"}}}"#);
+        }
+        assert_eq!(body["variables"]["login"], "octocat");
+        let affiliations = query
+            .split_once("ownerAffiliations: [")
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map_or("OWNER, COLLABORATOR", |(list, _)| list);
+        let nodes = VISIBLE
+            .iter()
+            .filter(|(_, _, affiliation)| affiliations.split(", ").any(|a| a == *affiliation))
+            .map(|(id, full_name, _)| {
+                json!({
+                    "databaseId": id,
+                    "name": full_name.split_once('/').unwrap().1,
+                    "nameWithOwner": full_name,
+                    "isArchived": false,
+                    "primaryLanguage": null,
+                    "url": format!("https://github.com/{full_name}"),
+                    "sshUrl": format!("git@github.com:{full_name}.git"),
+                    "diskUsage": 0,
+                })
+            })
+            .collect::<Vec<_>>();
+        let data = json!({ "data": { "user": { "repositories": {
+            "pageInfo": { "hasNextPage": false, "endCursor": null },
+            "nodes": nodes,
+        }}}});
+        Response::json(200, &data.to_string())
+    }
+
+    #[tokio::test]
+    async fn graphql_lists_the_same_user_repositories_as_rest() {
+        let server = TestServer::start(github).await;
+        let output = tempfile::tempdir().unwrap();
+        let config = test_support::config(&[
+            "octocat",
+            "-o",
+            output.path().to_str().unwrap(),
+            "--api-base-url",
+            &server.url,
+            "--no-http-cache",
+        ]);
+        let credentials = vec![Credential {
+            source: "test".to_string(),
+            token: "token".to_string(),
+        }];
+        let names = |repositories: Vec<Repository>| {
+            let mut names = repositories
+                .into_iter()
+                .map(|repository| repository.full_name)
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let rest = GitHubClient::from_runtime(&config.runtime, credentials.clone()).unwrap();
+        let listed = retrieve_repositories(&config, &rest)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let graphql = GraphQlClient::from_runtime(&config.runtime, credentials).unwrap();
+        let queried = retrieve_repositories_graphql(&config, &graphql)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            names(listed),
+            ["acme/team", "octocat/own", "someone/shared"]
+        );
+        assert_eq!(
+            names(queried),
+            ["acme/team", "octocat/own", "someone/shared"]
+        );
+    }
+
+    /// A bare remote with one commit, served by the API as `owner/app` of
+    /// `size` kilobytes, and the output directory backing it up.
+    struct Backup {
+        dir: tempfile::TempDir,
+        server: TestServer,
+    }
+
+    impl Backup {
+        async fn new(size: u64) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            git(
+                dir.path(),
+                &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+            );
+            git(dir.path(), &["clone", "--quiet", "remote.git", "work"]);
+            let work = dir.path().join("work");
+            git(&work, &["checkout", "--quiet", "-b", "main"]);
+            fs::write(work.join("file"), "one").unwrap();
+            git(&work, &["add", "file"]);
+            git(&work, &["commit", "--quiet", "-m", "one"]);
+            git(&work, &["push", "--quiet", "origin", "main"]);
+
+            let repository = json!({
+                "id": 1,
+                "name": "app",
+                "full_name": "owner/app",
+                "archived": false,
+                "language": null,
+                "clone_url": format!("file://{}", dir.path().join("remote.git").display()),
+                "ssh_url": "git@github.com:owner/app.git",
+                "size": size,
+            })
+            .to_string();
+            let server = TestServer::start(move |request| match request.path.as_str() {
+                "/repos/owner/app" => Response::json(200, &repository),
+                _ => Response::new(404),
+            })
+            .await;
+            Self { dir, server }
+        }
+
+        fn output(&self) -> PathBuf {
+            self.dir.path().join("output")
+        }
+
+        fn clone_dir(&self) -> PathBuf {
+            self.output().join("repositories/owner/app")
+        }
+
+        async fn run(&self, extra: &[&str]) -> Result<Vec<Value>> {
+            let output = self.output();
+            let mut args = vec![
+                "--repo",
+                "owner/app",
+                "-o",
+                output.to_str().unwrap(),
+                "--api-base-url",
+                &self.server.url,
+                "--no-http-cache",
+                "--no-credential-fallbacks",
+                "--skip-preflight",
+            ];
+            args.extend(extra);
+            backup_repositories(&test_support::config(&args)).await?;
+
+            let report =
+                serde_json::from_slice::<Value>(&fs::read(output.join("backup-report.json"))?)?;
+            Ok(report["repositories"].as_array().unwrap().clone())
+        }
+
+        fn state(&self) -> BackupState {
+            BackupState::load(&self.output().join("state.json")).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn clone_into_place_renames_finished_clones() {
+        let backup = Backup::new(0).await;
+        let root = backup.output().join("repositories");
+        let partial = root.join(PARTIAL_DIR).join("owner/app");
+        let url = format!("file://{}", backup.dir.path().join("remote.git").display());
+
+        clone_into_place(
+            &SubprocessBackend,
+            &url,
+            &partial,
+            &backup.clone_dir(),
+            &CloneMode::default(),
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert!(!partial.exists());
+        assert_eq!(
+            fs::read_to_string(backup.clone_dir().join("file")).unwrap(),
+            "one"
+        );
+
+        let missing = root.join("owner/missing");
+        let failed = clone_into_place(
+            &SubprocessBackend,
+            &format!("file://{}", backup.dir.path().join("missing.git").display()),
+            &root.join(PARTIAL_DIR).join("owner/missing"),
+            &missing,
+            &CloneMode::default(),
+            TIMEOUT,
+        )
+        .await;
+        assert!(failed.is_err());
+        assert!(!root.join(PARTIAL_DIR).join("owner/missing").exists());
+        assert!(!missing.exists());
+    }
+
+    #[tokio::test]
+    async fn clones_left_in_partial_are_discarded_and_cloned_again() {
+        let backup = Backup::new(0).await;
+        // An interrupted run leaves an incomplete clone under `.partial/`.
+        let partial = backup.output().join("repositories").join(PARTIAL_DIR);
+        fs::create_dir_all(partial.join("owner/app/.git")).unwrap();
+        fs::write(partial.join("owner/app/.git/HEAD"), "garbage").unwrap();
+
+        let reports = backup.run(&[]).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "cloned");
+        assert!(!partial.exists());
+        assert_eq!(
+            fs::read_to_string(backup.clone_dir().join("file")).unwrap(),
+            "one"
+        );
+    }
+
+    #[tokio::test]
+    async fn resumed_runs_skip_repositories_synced_since_the_interruption() {
+        let backup = Backup::new(0).await;
+        backup.run(&[]).await.unwrap();
+        let mut state = backup.state();
+        let synced_at = state.repositories[&1].synced_at.unwrap();
+
+        // Synced by the interrupted run: skipped.
+        state.resume_since = Some(synced_at);
+        state.save(&backup.output().join("state.json")).unwrap();
+        let reports = backup.run(&[]).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "skipped");
+        assert_eq!(backup.state().resume_since, None);
+
+        // Synced before the interrupted run started: updated again.
+        let mut state = backup.state();
+        state.resume_since = Some(synced_at + chrono::Duration::seconds(1));
+        state.save(&backup.output().join("state.json")).unwrap();
+        let reports = backup.run(&[]).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "updated");
+    }
+
+    #[test]
+    fn reserve_fails_once_new_clones_exceed_the_free_space() {
+        let mut space = SpaceBudget {
+            path: PathBuf::from("/backup"),
+            available: Some(1_000),
+            projected: 0,
+        };
+        space.reserve(600).unwrap();
+        space.reserve(400).unwrap();
+        assert!(matches!(
+            space.reserve(1),
+            Err(BackupError::InsufficientSpace {
+                required: 1_001,
+                available: 1_000,
+                ..
+            })
+        ));
+
+        // Unknown free space never fails.
+        let mut space = SpaceBudget {
+            path: PathBuf::from("/backup"),
+            available: None,
+            projected: 0,
+        };
+        space.reserve(u64::MAX).unwrap();
+    }
+
+    #[tokio::test]
+    async fn repositories_over_max_repo_size_are_not_cloned() {
+        let backup = Backup::new(2_048).await;
+
+        let reports = backup.run(&["--max-repo-size", "1M"]).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "too_large");
+        assert!(!backup.clone_dir().exists());
+
+        let reports = backup.run(&["--max-repo-size", "3M"]).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "cloned");
+    }
+
+    #[tokio::test]
+    async fn maintenance_is_recorded_in_the_state_and_the_report() {
+        let backup = Backup::new(0).await;
+        let maintenance = ["--maintenance", "repack"];
+
+        // New clones are not maintained.
+        let reports = backup.run(&maintenance).await.unwrap();
+        assert_eq!(reports[0]["maintenance"], Value::Null);
+        assert_eq!(backup.state().repositories[&1].maintained_at, None);
+
+        let reports = backup.run(&maintenance).await.unwrap();
+        assert_eq!(reports[0]["maintenance"]["task"], "repack");
+        assert!(reports[0]["maintenance"]["bytes_after"].as_u64().unwrap() > 0);
+        let maintained_at = backup.state().repositories[&1].maintained_at.unwrap();
+
+        // Not due again within --maintenance-interval-days.
+        let reports = backup.run(&maintenance).await.unwrap();
+        assert_eq!(reports[0]["outcome"], "updated");
+        assert_eq!(reports[0]["maintenance"], Value::Null);
+        assert_eq!(
+            backup.state().repositories[&1].maintained_at,
+            Some(maintained_at)
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+use crate::{error::Result, git::subprocess};
+
+/// Tips of local branches rewritten upstream are kept under
+/// `refs/backup/reset/<timestamp>/<branch>`, outside the snapshot timestamps.
+const RESET_NAMESPACE: &str = "refs/backup/reset";
+const RESET_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const REMOTE_PREFIX: &str = "refs/remotes/origin/";
+
+/// Tips of the remote-tracking branches, read before a fetch and passed to
+/// [`reset_to_remote`] after it.
+pub async fn remote_tips(clone_dir: &Path) -> Result<BTreeMap<String, String>> {
+    branches(clone_dir, REMOTE_PREFIX).await
+}
+
+/// Points every local branch at its remote-tracking branch after a fetch and
+/// hard-resets the default branch, which is refreshed from the remote, creating
+/// local branches for new remote ones. Local tips that are not contained in
+/// the new remote tip, because upstream force-pushed or the clone had local
+/// commits, are saved first. Returns the branches whose previous tip was
+/// saved.
+///
+/// Shallow clones lack the history to tell a force-push from a fast-forward,
+/// so only local tips that differ from `fetched_from`, the remote tips before
+/// the fetch, are saved there.
+pub async fn reset_to_remote(
+    clone_dir: &Path,
+    fetched_from: &BTreeMap<String, String>,
+    now: DateTime<Utc>,
+    timeout: Duration,
+) -> Result<Vec<String>> {
+    let remote = branches(clone_dir, REMOTE_PREFIX)
+        .await?
+        .into_iter()
+        .filter(|(branch, _)| branch != "HEAD")
+        .collect::<BTreeMap<_, _>>();
+    if remote.is_empty() {
+        return Ok(Vec::new());
+    }
+    let local = branches(clone_dir, "refs/heads/").await?;
+    let shallow = clone_dir.join(".git").join("shallow").exists();
+    let default = subprocess::remote_default_branch(clone_dir, timeout)
+        .await?
+        .filter(|branch| remote.contains_key(branch));
+
+    let timestamp = now.format(RESET_TIMESTAMP_FORMAT);
+    let mut saved = Vec::new();
+    let mut instructions = String::new();
+    for (branch, new) in &remote {
+        let old = local.get(branch);
+        if old == Some(new) {
+            continue;
+        }
+        if let Some(old) = old {
+            let rewritten = if shallow {
+                fetched_from.get(branch) != Some(old)
+            } else {
+                !subprocess::is_ancestor(clone_dir, old, new).await?
+            };
+            if rewritten {
+                instructions.push_str(&format!(
+                    "create {RESET_NAMESPACE}/{timestamp}/{branch} {old}\n"
+                ));
+                saved.push(branch.clone());
+            }
+        }
+        // The default branch is moved by the checkout below.
+        if default.as_ref() != Some(branch) {
+            instructions.push_str(&match old {
+                Some(old) => format!("update refs/heads/{branch} {new} {old}\n"),
+                None => format!("create refs/heads/{branch} {new}\n"),
+            });
+        }
+    }
+    if !instructions.is_empty() {
+        subprocess::update_refs(clone_dir, &instructions).await?;
+    }
+
+    if let Some(branch) = default {
+        subprocess::force_checkout(clone_dir, &branch, &format!("{REMOTE_PREFIX}{branch}")).await?;
+    }
+    if !saved.is_empty() {
+        info!(
+            path = %clone_dir.display(),
+            branches = ?saved,
+            backup = %format!("{RESET_NAMESPACE}/{timestamp}/"),
+            "branches rewritten upstream, saved previous tips before resetting",
+        );
+    }
+
+    Ok(saved)
+}
+
+/// Maps branch names under `prefix` to their object ids.
+async fn branches(clone_dir: &Path, prefix: &str) -> Result<BTreeMap<String, String>> {
+    Ok(
+        subprocess::list_refs(clone_dir, &[prefix.trim_end_matches('/')])
+            .await?
+            .into_iter()
+            .filter_map(|(oid, name)| Some((name.strip_prefix(prefix)?.to_string(), oid)))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        config::{CloneMode, UpdateStrategy},
+        test_support::git,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        remote: PathBuf,
+        work: PathBuf,
+        clone: PathBuf,
+    }
+
+    /// A bare remote with one commit on `main`, a working copy pushing to it
+    /// and a clone of it made with `mode`.
+    async fn fixture(mode: &CloneMode) -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        let work = dir.path().join("work");
+        let clone = dir.path().join("clone");
+        git(
+            dir.path(),
+            &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+        );
+        git(dir.path(), &["clone", "--quiet", "remote.git", "work"]);
+        git(&work, &["checkout", "--quiet", "-b", "main"]);
+        commit(&work, "one");
+        git(&work, &["push", "--quiet", "origin", "main"]);
+
+        let url = format!("file://{}", remote.display());
+        subprocess::clone_repository(&url, &clone, mode, TIMEOUT)
+            .await
+            .unwrap();
+        Fixture {
+            _dir: dir,
+            remote,
+            work,
+            clone,
+        }
+    }
+
+    fn commit(work: &Path, content: &str) -> String {
+        fs::write(work.join("file"), content).unwrap();
+        git(work, &["add", "file"]);
+        git(work, &["commit", "--quiet", "-m", content]);
+        git(work, &["rev-parse", "HEAD"])
+    }
+
+    async fn update(fixture: &Fixture, mode: &CloneMode, now: DateTime<Utc>) -> Vec<String> {
+        let fetched_from = remote_tips(&fixture.clone).await.unwrap();
+        subprocess::update_repository(&fixture.clone, mode, false, UpdateStrategy::Reset, TIMEOUT)
+            .await
+            .unwrap();
+        reset_to_remote(&fixture.clone, &fetched_from, now, TIMEOUT)
+            .await
+            .unwrap()
+    }
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 1, hour, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn saves_tips_rewritten_by_force_push() {
+        let mode = CloneMode::default();
+        let fixture = fixture(&mode).await;
+        let original = git(&fixture.clone, &["rev-parse", "HEAD"]);
+
+        let fast_forward = commit(&fixture.work, "two");
+        git(&fixture.work, &["push", "--quiet", "origin", "main"]);
+        assert!(update(&fixture, &mode, at(1)).await.is_empty());
+        assert_eq!(git(&fixture.clone, &["rev-parse", "HEAD"]), fast_forward);
+
+        git(&fixture.work, &["reset", "--quiet", "--hard", &original]);
+        let rewritten = commit(&fixture.work, "rewritten");
+        git(
+            &fixture.work,
+            &["push", "--quiet", "--force", "origin", "main"],
+        );
+        assert_eq!(update(&fixture, &mode, at(2)).await, vec!["main"]);
+
+        assert_eq!(git(&fixture.clone, &["rev-parse", "HEAD"]), rewritten);
+        assert_eq!(
+            fs::read_to_string(fixture.clone.join("file")).unwrap(),
+            "rewritten"
+        );
+        assert_eq!(
+            git(
+                &fixture.clone,
+                &["rev-parse", "refs/backup/reset/20260301T020000Z/main"]
+            ),
+            fast_forward
+        );
+
+        assert!(update(&fixture, &mode, at(3)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn shallow_clones_do_not_save_fast_forwarded_tips() {
+        let mode = CloneMode {
+            depth: Some(1),
+            ..CloneMode::default()
+        };
+        let fixture = fixture(&mode).await;
+        assert!(fixture.clone.join(".git/shallow").exists());
+
+        for (hour, content) in [(1, "two"), (2, "three")] {
+            let tip = commit(&fixture.work, content);
+            git(&fixture.work, &["push", "--quiet", "origin", "main"]);
+            assert!(update(&fixture, &mode, at(hour)).await.is_empty());
+            assert_eq!(git(&fixture.clone, &["rev-parse", "HEAD"]), tip);
+        }
+        assert_eq!(
+            git(&fixture.clone, &["for-each-ref", "refs/backup/reset"]),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn fast_forward_updates_shallow_clones_past_the_boundary() {
+        let mode = CloneMode {
+            depth: Some(1),
+            ..CloneMode::default()
+        };
+        let fixture = fixture(&mode).await;
+
+        for content in ["two", "three"] {
+            let tip = commit(&fixture.work, content);
+            git(&fixture.work, &["push", "--quiet", "origin", "main"]);
+            subprocess::update_repository(
+                &fixture.clone,
+                &mode,
+                false,
+                UpdateStrategy::FastForward,
+                TIMEOUT,
+            )
+            .await
+            .unwrap();
+            assert_eq!(git(&fixture.clone, &["rev-parse", "HEAD"]), tip);
+            assert_eq!(
+                fs::read_to_string(fixture.clone.join("file")).unwrap(),
+                content
+            );
+        }
+        assert_eq!(
+            git(&fixture.clone, &["symbolic-ref", "--short", "HEAD"]),
+            "main"
+        );
+    }
+
+    #[tokio::test]
+    async fn follows_default_branch_changes() {
+        let mode = CloneMode::default();
+        let fixture = fixture(&mode).await;
+
+        git(&fixture.work, &["checkout", "--quiet", "-b", "develop"]);
+        let develop = commit(&fixture.work, "develop");
+        git(&fixture.work, &["push", "--quiet", "origin", "develop"]);
+        git(
+            &fixture.remote,
+            &["symbolic-ref", "HEAD", "refs/heads/develop"],
+        );
+
+        assert!(update(&fixture, &mode, at(1)).await.is_empty());
+        assert_eq!(
+            git(
+                &fixture.clone,
+                &["symbolic-ref", "refs/remotes/origin/HEAD"]
+            ),
+            "refs/remotes/origin/develop"
+        );
+        assert_eq!(
+            git(&fixture.clone, &["symbolic-ref", "--short", "HEAD"]),
+            "develop"
+        );
+        assert_eq!(git(&fixture.clone, &["rev-parse", "HEAD"]), develop);
+    }
+}
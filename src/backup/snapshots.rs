@@ -0,0 +1,288 @@
+use std::{collections::BTreeSet, path::Path};
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use tracing::info;
+
+use crate::{config::RetentionPolicy, error::Result, git::subprocess};
+
+const SNAPSHOT_NAMESPACE: &str = "refs/backup";
+const SNAPSHOT_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const SNAPSHOT_SOURCES: [&str; 3] = ["refs/heads", "refs/tags", "refs/remotes"];
+
+/// Copies the current branches, tags and remote-tracking refs under
+/// `refs/backup/<timestamp>/` so a later force-push upstream cannot drop the
+/// history they point to. Skips the snapshot when nothing changed since the
+/// newest one. Returns the snapshot timestamp when one was written.
+pub async fn create_snapshot(clone_dir: &Path, now: DateTime<Utc>) -> Result<Option<String>> {
+    let current = subprocess::list_refs(clone_dir, &SNAPSHOT_SOURCES).await?;
+    if current.is_empty() {
+        return Ok(None);
+    }
+
+    let snapshots = subprocess::list_refs(clone_dir, &[SNAPSHOT_NAMESPACE]).await?;
+    if let Some(latest) = snapshot_timestamps(&snapshots).last() {
+        let prefix = format!("{SNAPSHOT_NAMESPACE}/{}/", format_timestamp(latest));
+        let latest_refs = snapshots
+            .iter()
+            .filter_map(|(oid, name)| {
+                name.strip_prefix(&prefix)
+                    .map(|rest| (oid.clone(), format!("refs/{rest}")))
+            })
+            .collect::<BTreeSet<_>>();
+
+        if latest_refs == current.iter().cloned().collect() {
+            return Ok(None);
+        }
+    }
+
+    let timestamp = format_timestamp(&now);
+    let instructions = current
+        .iter()
+        .filter_map(|(oid, name)| {
+            name.strip_prefix("refs/")
+                .map(|rest| format!("create {SNAPSHOT_NAMESPACE}/{timestamp}/{rest} {oid}\n"))
+        })
+        .collect::<String>();
+    subprocess::update_refs(clone_dir, &instructions).await?;
+
+    Ok(Some(timestamp))
+}
+
+/// Deletes snapshots that fall outside the retention policy. Returns the
+/// number of snapshots removed.
+pub async fn prune_snapshots(clone_dir: &Path, retention: &RetentionPolicy) -> Result<usize> {
+    let snapshots = subprocess::list_refs(clone_dir, &[SNAPSHOT_NAMESPACE]).await?;
+    let timestamps = snapshot_timestamps(&snapshots);
+    let keep = snapshots_to_keep(&timestamps, retention);
+
+    let expired = timestamps
+        .iter()
+        .filter(|timestamp| !keep.contains(timestamp))
+        .map(|timestamp| format!("{SNAPSHOT_NAMESPACE}/{}/", format_timestamp(timestamp)))
+        .collect::<Vec<_>>();
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    let instructions = snapshots
+        .iter()
+        .filter(|(_, name)| expired.iter().any(|prefix| name.starts_with(prefix)))
+        .map(|(oid, name)| format!("delete {name} {oid}\n"))
+        .collect::<String>();
+    subprocess::update_refs(clone_dir, &instructions).await?;
+
+    info!(path = %clone_dir.display(), removed = expired.len(), "pruned expired snapshots");
+    Ok(expired.len())
+}
+
+/// Selects the snapshots retained by a keep-last/daily/weekly/monthly policy.
+/// Each period keeps its newest snapshot, for the most recent N periods.
+pub fn snapshots_to_keep(
+    timestamps: &[DateTime<Utc>],
+    retention: &RetentionPolicy,
+) -> BTreeSet<DateTime<Utc>> {
+    let mut newest_first = timestamps.to_vec();
+    newest_first.sort_unstable_by(|left, right| right.cmp(left));
+
+    let mut keep = newest_first
+        .iter()
+        .take(retention.keep_last)
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    keep_newest_per_period(&newest_first, retention.keep_daily, &mut keep, |t| {
+        (t.year(), t.ordinal())
+    });
+    keep_newest_per_period(&newest_first, retention.keep_weekly, &mut keep, |t| {
+        let week = t.iso_week();
+        (week.year(), week.week())
+    });
+    keep_newest_per_period(&newest_first, retention.keep_monthly, &mut keep, |t| {
+        (t.year(), t.month())
+    });
+
+    keep
+}
+
+fn keep_newest_per_period<K: PartialEq>(
+    newest_first: &[DateTime<Utc>],
+    periods: usize,
+    keep: &mut BTreeSet<DateTime<Utc>>,
+    period_of: impl Fn(&DateTime<Utc>) -> K,
+) {
+    let mut last_period = None;
+    let mut kept = 0;
+
+    for timestamp in newest_first {
+        if kept >= periods {
+            break;
+        }
+
+        let period = period_of(timestamp);
+        if last_period.as_ref() != Some(&period) {
+            keep.insert(*timestamp);
+            last_period = Some(period);
+            kept += 1;
+        }
+    }
+}
+
+fn snapshot_timestamps(snapshots: &[(String, String)]) -> Vec<DateTime<Utc>> {
+    snapshots
+        .iter()
+        .filter_map(|(_, name)| name.strip_prefix(SNAPSHOT_NAMESPACE)?.split('/').nth(1))
+        .filter_map(|segment| {
+            NaiveDateTime::parse_from_str(segment, SNAPSHOT_TIMESTAMP_FORMAT).ok()
+        })
+        .map(|naive| naive.and_utc())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format(SNAPSHOT_TIMESTAMP_FORMAT).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+    use crate::{config::CloneMode, test_support::git};
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+    }
+
+    fn policy(keep_last: usize, daily: usize, weekly: usize, monthly: usize) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last,
+            keep_daily: daily,
+            keep_weekly: weekly,
+            keep_monthly: monthly,
+        }
+    }
+
+    #[test]
+    fn keeps_newest_snapshot_per_day() {
+        let timestamps = [at(1, 1), at(1, 9), at(2, 3), at(3, 4), at(3, 20)];
+        let keep = snapshots_to_keep(&timestamps, &policy(0, 2, 0, 0));
+        assert_eq!(
+            keep.into_iter().collect::<Vec<_>>(),
+            vec![at(2, 3), at(3, 20)]
+        );
+    }
+
+    #[test]
+    fn combines_last_and_period_rules() {
+        let timestamps = [at(2, 1), at(9, 1), at(16, 1), at(16, 2), at(16, 3)];
+        let keep = snapshots_to_keep(&timestamps, &policy(2, 0, 2, 0));
+        assert_eq!(
+            keep.into_iter().collect::<Vec<_>>(),
+            vec![at(9, 1), at(16, 2), at(16, 3)]
+        );
+    }
+
+    #[test]
+    fn empty_policy_keeps_nothing() {
+        let keep = snapshots_to_keep(&[at(1, 1)], &policy(0, 0, 0, 0));
+        assert!(keep.is_empty());
+    }
+
+    /// A clone of a bare remote with one commit on `main` and a tag.
+    async fn clone_fixture() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let work = dir.path().join("work");
+        let clone = dir.path().join("clone");
+        git(
+            dir.path(),
+            &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+        );
+        git(dir.path(), &["clone", "--quiet", "remote.git", "work"]);
+        git(&work, &["checkout", "--quiet", "-b", "main"]);
+        commit(&work, "one");
+        git(&work, &["tag", "v1"]);
+        git(&work, &["push", "--quiet", "origin", "main", "v1"]);
+
+        let url = format!("file://{}", dir.path().join("remote.git").display());
+        subprocess::clone_repository(&url, &clone, &CloneMode::default(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        (dir, clone)
+    }
+
+    fn commit(work: &Path, content: &str) {
+        fs::write(work.join("file"), content).unwrap();
+        git(work, &["add", "file"]);
+        git(work, &["commit", "--quiet", "-m", content]);
+    }
+
+    fn snapshot_refs(clone: &Path) -> Vec<String> {
+        let refs = git(
+            clone,
+            &[
+                "for-each-ref",
+                "--format=%(refname) %(objectname)",
+                SNAPSHOT_NAMESPACE,
+            ],
+        );
+        refs.lines().map(ToString::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn snapshots_copy_refs_and_pruning_deletes_them() {
+        let (_dir, clone) = clone_fixture().await;
+        let head = git(&clone, &["rev-parse", "HEAD"]);
+
+        assert_eq!(
+            create_snapshot(&clone, at(1, 1)).await.unwrap().as_deref(),
+            Some("20260301T010000Z")
+        );
+        let refs = snapshot_refs(&clone);
+        for name in ["heads/main", "remotes/origin/main", "tags/v1"] {
+            assert!(
+                refs.contains(&format!("refs/backup/20260301T010000Z/{name} {head}")),
+                "{refs:?}"
+            );
+        }
+
+        // Unchanged refs are not saved again.
+        assert_eq!(create_snapshot(&clone, at(1, 2)).await.unwrap(), None);
+
+        git(
+            &clone,
+            &["commit", "--quiet", "--allow-empty", "-m", "local"],
+        );
+        assert_eq!(
+            create_snapshot(&clone, at(2, 1)).await.unwrap().as_deref(),
+            Some("20260302T010000Z")
+        );
+
+        assert_eq!(
+            prune_snapshots(&clone, &policy(1, 0, 0, 0)).await.unwrap(),
+            1
+        );
+        let refs = snapshot_refs(&clone);
+        assert!(
+            refs.iter()
+                .all(|name| name.starts_with("refs/backup/20260302T010000Z/")),
+            "{refs:?}"
+        );
+        let sources = git(
+            &clone,
+            &["for-each-ref", "refs/heads", "refs/tags", "refs/remotes"],
+        );
+        assert_eq!(refs.len(), sources.lines().count());
+        assert_eq!(
+            prune_snapshots(&clone, &policy(1, 0, 0, 0)).await.unwrap(),
+            0
+        );
+    }
+}
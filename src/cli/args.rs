@@ -2,6 +2,10 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
+use crate::config::{
+    parse_size, ArchiveFormat, GitBackendKind, MaintenanceTask, OrphanPolicy, UpdateStrategy,
+};
+
 #[derive(Debug, Clone, Parser)]
 #[command(
     name = "github-backup",
@@ -21,27 +25,171 @@ pub struct CliArgs {
     #[arg(long, env = "GITHUB_TOKEN")]
     pub token: Option<String>,
 
-    #[arg(long = "token-file")]
-    pub token_file: Option<PathBuf>,
+    /// File containing a token; repeat to rotate between several tokens
+    #[arg(long = "token-file", value_name = "PATH")]
+    pub token_files: Vec<PathBuf>,
 
+    /// Read the token from the system secret store (macOS Keychain, Secret
+    /// Service) or the encrypted token file saved by --store-token
     #[arg(long)]
     pub use_keychain: bool,
 
+    /// Secret store service name [default: github-backup-rs]
     #[arg(long)]
     pub keychain_service: Option<String>,
 
+    /// Base64 AES-256 key in FILE protecting the token file used where no
+    /// secret store is available
+    #[arg(long, value_name = "FILE")]
+    pub keychain_key_file: Option<PathBuf>,
+
+    /// Do not reuse tokens from gh, git credential helpers or GH_TOKEN
+    #[arg(long)]
+    pub no_credential_fallbacks: bool,
+
+    /// Save the token given by --token or --token-file in the secret store,
+    /// then exit
+    #[arg(long)]
+    pub store_token: bool,
+
+    /// Do not check token scopes and permissions before the backup
+    #[arg(long)]
+    pub skip_preflight: bool,
+
     #[arg(long = "repo", value_name = "OWNER/REPO")]
     pub repositories: Vec<String>,
 
     #[arg(long, default_value_t = 4)]
     pub concurrency: usize,
 
+    /// Maximum number of listing pages fetched at the same time
+    #[arg(long, default_value_t = 4)]
+    pub page_concurrency: usize,
+
+    /// Times a request rejected by a rate limit is sent again before failing
     #[arg(long, default_value_t = 5)]
     pub max_retries: u32,
 
+    /// API requests to leave unused for other tools sharing the token
+    #[arg(long, default_value_t = 100)]
+    pub rate_limit_reserve: u64,
+
     #[arg(long, default_value_t = 30)]
     pub request_timeout_seconds: u64,
 
+    /// Seconds a git clone, fetch or bundle may run before it is killed.
+    #[arg(long, default_value_t = 3600)]
+    pub git_timeout_seconds: u64,
+
+    /// Implementation used to clone and fetch repositories
+    #[arg(long, value_enum, default_value_t = GitBackendKind::Subprocess)]
+    pub git_backend: GitBackendKind,
+
     #[arg(long)]
     pub api_base_url: Option<String>,
+
+    /// Do not cache API responses for conditional requests
+    #[arg(long)]
+    pub no_http_cache: bool,
+
+    /// List repositories through the GraphQL API (requires a token)
+    #[arg(long)]
+    pub graphql: bool,
+
+    /// What to do with clones of repositories that were deleted upstream
+    #[arg(long, value_enum, default_value_t = OrphanPolicy::Keep)]
+    pub orphan_policy: OrphanPolicy,
+
+    /// Days broken clones are kept in repositories/_quarantine/; 0 keeps them
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    pub quarantine_retention_days: u32,
+
+    /// Save existing refs under refs/backup/<timestamp>/ before each update
+    #[arg(long)]
+    pub snapshots: bool,
+
+    /// Number of most recent snapshots to keep
+    #[arg(long, default_value_t = 1)]
+    pub keep_last: usize,
+
+    /// Number of days for which the newest snapshot is kept
+    #[arg(long, default_value_t = 7)]
+    pub keep_daily: usize,
+
+    /// Number of weeks for which the newest snapshot is kept
+    #[arg(long, default_value_t = 4)]
+    pub keep_weekly: usize,
+
+    /// Number of months for which the newest snapshot is kept
+    #[arg(long, default_value_t = 12)]
+    pub keep_monthly: usize,
+
+    /// Repack existing clones after updating them: maintenance, gc-auto or repack
+    #[arg(long, value_enum, value_name = "TASK")]
+    pub maintenance: Option<MaintenanceTask>,
+
+    /// Days between maintenance runs of the same clone; 0 runs it every time
+    #[arg(long, value_name = "DAYS", default_value_t = 7)]
+    pub maintenance_interval_days: u32,
+
+    /// Clone only the newest N commits of each branch
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+
+    /// Clone only commits newer than a date, e.g. 2024-01-01
+    #[arg(long, value_name = "DATE")]
+    pub shallow_since: Option<String>,
+
+    /// Partial clone filter: blob:none or blob:limit=<size>
+    #[arg(long, value_name = "SPEC")]
+    pub filter: Option<String>,
+
+    /// Fetch the full history of shallow and partial clones
+    #[arg(long, conflicts_with_all = ["depth", "shallow_since", "filter"])]
+    pub unshallow: bool,
+
+    /// How existing clones follow upstream; reset survives force-pushes
+    #[arg(long, value_enum, default_value_t = UpdateStrategy::FastForward)]
+    pub update_strategy: UpdateStrategy,
+
+    /// Skip repositories GitHub reports as larger than this, e.g. 500M or 2G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_repo_size: Option<u64>,
+
+    /// Initialize and update submodules recursively after each clone or fetch
+    #[arg(long)]
+    pub submodules: bool,
+
+    /// Also back up every repository referenced as a submodule, once per URL
+    #[arg(long)]
+    pub backup_submodules: bool,
+
+    /// Also back up issues and pull requests with their labels and comments
+    /// under issues/, through the GraphQL API (requires a token)
+    #[arg(long)]
+    pub issues: bool,
+
+    /// Also package each repository as a single archive file under archives/
+    #[arg(long, value_enum)]
+    pub archive_format: Option<ArchiveFormat>,
+
+    /// Write metadata and archives to object storage, e.g. s3://bucket/prefix
+    #[arg(long, value_name = "URL")]
+    pub storage_url: Option<String>,
+
+    /// S3-compatible endpoint, e.g. http://localhost:9000 for MinIO
+    #[arg(long, env = "AWS_ENDPOINT_URL")]
+    pub s3_endpoint: Option<String>,
+
+    /// Region used to sign S3 requests
+    #[arg(long, env = "AWS_REGION", default_value = "us-east-1")]
+    pub s3_region: String,
+
+    /// Encrypt the inventory and archives with the base64 AES-256 key in FILE
+    #[arg(long, value_name = "FILE")]
+    pub encryption_key_file: Option<PathBuf>,
+
+    /// Decrypt a .enc file, or every .enc file in a directory, then exit
+    #[arg(long, value_name = "PATH", requires = "encryption_key_file")]
+    pub decrypt: Option<PathBuf>,
 }
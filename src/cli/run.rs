@@ -1,11 +1,55 @@
 use tracing::info;
 
-use crate::{backup::BackupOrchestrator, config::BackupConfig, error::Result};
+use crate::{
+    auth::{self, keychain},
+    backup::BackupOrchestrator,
+    config::{AuthConfig, BackupConfig},
+    crypto::{self, EncryptionKey},
+    error::{AuthError, Result},
+    shutdown,
+};
 
 use super::args::CliArgs;
 
 pub async fn run_cli(args: CliArgs) -> Result<()> {
+    if let (Some(path), Some(key_file)) = (&args.decrypt, &args.encryption_key_file) {
+        let key = EncryptionKey::from_file(key_file)?;
+        let restored = crypto::decrypt_path(&key, path)?;
+        info!(path = %path.display(), restored, "decryption finished");
+        return Ok(());
+    }
+
+    if args.store_token {
+        // Only a token the user named is stored, never one borrowed from
+        // another tool, and never one of several picked silently.
+        let given = usize::from(args.token.is_some()) + args.token_files.len();
+        if given != 1 {
+            return Err(AuthError::InvalidConfig(format!(
+                "--store-token needs exactly one token from --token, GITHUB_TOKEN or \
+                 --token-file, {given} given"
+            ))
+            .into());
+        }
+        let auth = AuthConfig {
+            use_keychain: false,
+            credential_fallbacks: false,
+            ..AuthConfig::from_cli(&args)
+        };
+        let token = auth::resolve_token(&auth)
+            .await?
+            .ok_or(AuthError::MissingToken)?;
+        let location = keychain::store_token_in_keychain(
+            args.keychain_service.as_deref(),
+            &token,
+            args.keychain_key_file.as_deref(),
+        )
+        .await?;
+        info!(location = %location, "stored token");
+        return Ok(());
+    }
+
     let config = BackupConfig::from_cli(&args)?;
     info!("starting backup run from CLI");
+    shutdown::listen_for_signals();
     BackupOrchestrator::new(config).run().await
 }
@@ -1,18 +1,48 @@
 use std::path::PathBuf;
 
+use clap::ValueEnum;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     cli::args::CliArgs,
     error::{BackupError, Result},
+    git,
 };
 
+const DEFAULT_API_BASE_URL: &str = "https://api.github.com";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupConfig {
     pub scope: BackupScope,
     pub output_dir: PathBuf,
     pub auth: AuthConfig,
     pub runtime: RuntimeConfig,
+    pub orphan_policy: OrphanPolicy,
+    /// Quarantined broken clones older than this are removed; `0` keeps
+    /// them.
+    pub quarantine_retention_days: u32,
+    pub snapshots: SnapshotConfig,
+    pub maintenance: MaintenanceConfig,
+    /// History fetched by new clones; existing clones keep the mode stored
+    /// in their state.
+    pub clone_mode: CloneMode,
+    /// Convert shallow and partial clones to full clones.
+    pub unshallow: bool,
+    pub update_strategy: UpdateStrategy,
+    /// Repositories GitHub reports as larger than this many bytes are
+    /// skipped.
+    pub max_repo_size: Option<u64>,
+    /// Update submodules recursively in every clone.
+    pub submodules: bool,
+    /// Back up the repositories referenced as submodules under
+    /// `repositories/_submodules/`.
+    pub backup_submodules: bool,
+    /// Back up issues and pull requests under `issues/`.
+    pub backup_issues: bool,
+    pub archive_format: Option<ArchiveFormat>,
+    pub storage: StorageConfig,
+    pub encryption_key_file: Option<PathBuf>,
 }
 
 impl BackupConfig {
@@ -32,21 +62,53 @@ impl BackupConfig {
         let config = Self {
             scope,
             output_dir: args.output_dir.clone(),
-            auth: AuthConfig {
-                token: args.token.clone(),
-                token_file: args.token_file.clone(),
-                use_keychain: args.use_keychain,
-                keychain_service: args.keychain_service.clone(),
-            },
+            auth: AuthConfig::from_cli(args),
             runtime: RuntimeConfig {
                 concurrency: args.concurrency,
+                page_concurrency: args.page_concurrency,
                 max_retries: args.max_retries,
+                rate_limit_reserve: args.rate_limit_reserve,
                 request_timeout_seconds: args.request_timeout_seconds,
+                git_timeout_seconds: args.git_timeout_seconds,
+                git_backend: args.git_backend,
                 api_base_url: args
                     .api_base_url
                     .clone()
-                    .unwrap_or_else(|| "https://api.github.com".to_string()),
+                    .unwrap_or_else(|| DEFAULT_API_BASE_URL.to_string()),
+                http_cache_dir: (!args.no_http_cache)
+                    .then(|| args.output_dir.join(".cache").join("http")),
+                http_cache_key_file: args.encryption_key_file.clone(),
+                use_graphql: args.graphql,
+            },
+            orphan_policy: args.orphan_policy,
+            quarantine_retention_days: args.quarantine_retention_days,
+            snapshots: SnapshotConfig {
+                enabled: args.snapshots,
+                retention: RetentionPolicy {
+                    keep_last: args.keep_last,
+                    keep_daily: args.keep_daily,
+                    keep_weekly: args.keep_weekly,
+                    keep_monthly: args.keep_monthly,
+                },
             },
+            maintenance: MaintenanceConfig {
+                task: args.maintenance,
+                interval_days: args.maintenance_interval_days,
+            },
+            clone_mode: CloneMode {
+                depth: args.depth,
+                shallow_since: args.shallow_since.clone(),
+                filter: args.filter.clone(),
+            },
+            unshallow: args.unshallow,
+            update_strategy: args.update_strategy,
+            max_repo_size: args.max_repo_size,
+            submodules: args.submodules,
+            backup_submodules: args.backup_submodules,
+            backup_issues: args.issues,
+            archive_format: args.archive_format,
+            storage: StorageConfig::from_cli(args)?,
+            encryption_key_file: args.encryption_key_file.clone(),
         };
 
         config.validate()?;
@@ -60,12 +122,51 @@ impl BackupConfig {
             ));
         }
 
+        if self.runtime.page_concurrency == 0 {
+            return Err(BackupError::Config(
+                "page_concurrency must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.runtime.git_timeout_seconds == 0 {
+            return Err(BackupError::Config(
+                "git_timeout_seconds must be greater than 0".to_string(),
+            ));
+        }
+
+        git::backend::backend(self.runtime.git_backend)?;
+
+        let git_options = self.options_running_git();
+        if self.runtime.git_backend == GitBackendKind::Gix
+            && !git_options.is_empty()
+            && !git::subprocess::is_installed()
+        {
+            return Err(BackupError::Config(format!(
+                "git was not found on PATH, but {} still run it with --git-backend gix",
+                git_options.join(", ")
+            )));
+        }
+
         if self.runtime.max_retries > 20 {
             return Err(BackupError::Config(
                 "max_retries must be less than or equal to 20".to_string(),
             ));
         }
 
+        if let Some(filter) = &self.clone_mode.filter {
+            if !is_supported_filter(filter) {
+                return Err(BackupError::Config(format!(
+                    "unsupported filter '{filter}', use blob:none or blob:limit=<size>"
+                )));
+            }
+        }
+
+        if self.snapshots.enabled && self.snapshots.retention.is_empty() {
+            return Err(BackupError::Config(
+                "snapshot retention must keep at least one snapshot".to_string(),
+            ));
+        }
+
         if matches!(self.scope, BackupScope::Unknown) {
             return Err(BackupError::Config(
                 "target argument is required (or use --repo owner/repo)".to_string(),
@@ -74,6 +175,25 @@ impl BackupConfig {
 
         Ok(())
     }
+
+    /// Enabled options that always run the `git` executable, whatever the
+    /// selected backend.
+    fn options_running_git(&self) -> Vec<&'static str> {
+        [
+            (self.snapshots.enabled, "--snapshots"),
+            (
+                self.update_strategy == UpdateStrategy::Reset,
+                "--update-strategy reset",
+            ),
+            (self.submodules, "--submodules"),
+            (self.backup_submodules, "--backup-submodules"),
+            (self.archive_format.is_some(), "--archive-format"),
+            (self.maintenance.task.is_some(), "--maintenance"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, option)| enabled.then_some(option))
+        .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,18 +205,312 @@ pub enum BackupScope {
     Unknown,
 }
 
+/// What to do with local clones whose repository no longer appears upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanPolicy {
+    /// Leave the clone in place.
+    #[default]
+    Keep,
+    /// Move the clone under `repositories/_archived/<date>/`.
+    Archive,
+    /// Remove the clone from disk.
+    Delete,
+}
+
+/// Single-file packaging written next to each clone for cold storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    /// `git bundle create --all`, restorable with `git clone <file>`.
+    Bundle,
+    /// zstd-compressed tarball of the whole clone directory.
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Bundle => "bundle",
+            Self::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// Implementation used for clones and fetches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GitBackendKind {
+    /// Run the `git` executable found on `PATH`.
+    #[default]
+    Subprocess,
+    /// Use gitoxide, a pure-Rust implementation (requires the `gix` feature).
+    Gix,
+}
+
+/// How existing clones follow upstream after a fetch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStrategy {
+    /// Fast-forward the checked out branch, failing when upstream rewrote it.
+    #[default]
+    FastForward,
+    /// Hard-reset the default branch to the remote and point every local
+    /// branch at its remote-tracking branch, saving rewritten tips first.
+    Reset,
+}
+
+/// How much history a clone holds: everything by default, or a shallow
+/// (`depth`, `shallow_since`) and/or partial (`filter`) clone.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CloneMode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shallow_since: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+impl CloneMode {
+    pub fn is_full(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_shallow(&self) -> bool {
+        self.depth.is_some() || self.shallow_since.is_some()
+    }
+}
+
+/// Parses a byte size such as `500M` or `2GiB`: a whole number with an
+/// optional binary `K`, `M`, `G` or `T` unit, case-insensitive.
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    let unit_start = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(unit_start);
+    let number = digits
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size '{value}', expected e.g. 500M or 2G"))?;
+    let shift = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        _ => return Err(format!("unknown size unit in '{value}', use K, M, G or T")),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{value}' is too large"))
+}
+
+/// Accepts the blob filters of `git clone --filter`: `blob:none` and
+/// `blob:limit=<n>[k|m|g]`.
+fn is_supported_filter(filter: &str) -> bool {
+    if filter == "blob:none" {
+        return true;
+    }
+
+    let Some(limit) = filter.strip_prefix("blob:limit=") else {
+        return false;
+    };
+    let digits = limit.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G']);
+    limit.len() - digits.len() <= 1
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    pub retention: RetentionPolicy,
+}
+
+/// Housekeeping run on existing clones after they are updated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Disabled when `None`.
+    pub task: Option<MaintenanceTask>,
+    /// Days to wait after a clone's last maintenance before the next one.
+    pub interval_days: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTask {
+    /// `git maintenance run`: the tasks configured for the clone, gc by default.
+    Maintenance,
+    /// `git gc --auto`: only packs when loose objects or packs pile up.
+    GcAuto,
+    /// `git repack -a -d`: rewrites all objects into a single pack.
+    Repack,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0
+            && self.keep_daily == 0
+            && self.keep_weekly == 0
+            && self.keep_monthly == 0
+    }
+}
+
+/// Where metadata files and archives are written. Clones always stay in the
+/// local output directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StorageConfig {
+    Local,
+    S3(S3Config),
+}
+
+impl StorageConfig {
+    fn from_cli(args: &CliArgs) -> Result<Self> {
+        let Some(url) = &args.storage_url else {
+            return Ok(Self::Local);
+        };
+
+        let location = url.strip_prefix("s3://").ok_or_else(|| {
+            BackupError::Config(format!("storage url '{url}' must start with s3://"))
+        })?;
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            return Err(BackupError::Config(format!(
+                "storage url '{url}' is missing a bucket name"
+            )));
+        }
+
+        Ok(Self::S3(S3Config {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            endpoint: args
+                .s3_endpoint
+                .clone()
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", args.s3_region)),
+            region: args.s3_region.clone(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub prefix: String,
+    pub endpoint: String,
+    pub region: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub token: Option<String>,
-    pub token_file: Option<PathBuf>,
+    pub token_files: Vec<PathBuf>,
     pub use_keychain: bool,
     pub keychain_service: Option<String>,
+    /// Key for the encrypted token file used when no secret store exists.
+    pub keychain_key_file: Option<PathBuf>,
+    /// Reuse tokens from `gh`, git credential helpers or `GH_TOKEN` when no
+    /// token is configured.
+    pub credential_fallbacks: bool,
+    /// GitHub host the tokens belong to, e.g. `github.com`.
+    pub host: String,
+    pub preflight: bool,
+}
+
+impl AuthConfig {
+    pub fn from_cli(args: &CliArgs) -> Self {
+        Self {
+            token: args.token.clone(),
+            token_files: args.token_files.clone(),
+            use_keychain: args.use_keychain,
+            keychain_service: args.keychain_service.clone(),
+            keychain_key_file: args.keychain_key_file.clone(),
+            credential_fallbacks: !args.no_credential_fallbacks,
+            host: web_host(args.api_base_url.as_deref().unwrap_or(DEFAULT_API_BASE_URL)),
+            preflight: !args.skip_preflight,
+        }
+    }
+}
+
+/// Web host of an API base URL, which is where `gh` and git credential
+/// helpers keep tokens: `api.github.com` becomes `github.com`, while GitHub
+/// Enterprise Server serves its API from the web host under `/api/v3`.
+fn web_host(api_base_url: &str) -> String {
+    let Some(url) = Url::parse(api_base_url).ok() else {
+        return "github.com".to_string();
+    };
+    let host = url.host_str().unwrap_or("github.com");
+    let host = host.strip_prefix("api.").unwrap_or(host);
+
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeConfig {
     pub concurrency: usize,
+    pub page_concurrency: usize,
     pub max_retries: u32,
+    pub rate_limit_reserve: u64,
     pub request_timeout_seconds: u64,
+    pub git_timeout_seconds: u64,
+    pub git_backend: GitBackendKind,
     pub api_base_url: String,
+    pub http_cache_dir: Option<PathBuf>,
+    /// Encrypts cached responses; the `--encryption-key-file` of the backup.
+    pub http_cache_key_file: Option<PathBuf>,
+    pub use_graphql: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_binary_units() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("10kb"), Ok(10 << 10));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("99999999T").is_err());
+    }
+
+    #[test]
+    fn lists_options_that_run_git_whatever_the_backend() {
+        let config = crate::test_support::config(&["acme", "-o", "out"]);
+        assert!(config.options_running_git().is_empty());
+
+        let config = crate::test_support::config(&[
+            "acme",
+            "-o",
+            "out",
+            "--snapshots",
+            "--update-strategy",
+            "reset",
+            "--backup-submodules",
+        ]);
+        assert_eq!(
+            config.options_running_git(),
+            [
+                "--snapshots",
+                "--update-strategy reset",
+                "--backup-submodules"
+            ]
+        );
+    }
 }
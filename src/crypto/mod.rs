@@ -0,0 +1,282 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    aead::{consts::U12, rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tracing::info;
+
+use crate::{
+    error::{CryptoError, Result},
+    io::atomic_write::write_atomic_with,
+};
+
+/// Suffix appended to the storage key of every encrypted artifact.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+const MAGIC: &[u8; 8] = b"GHBKENC1";
+const NONCE_PREFIX_LEN: usize = 8;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// 256-bit AES-GCM key read from a file containing its base64 encoding,
+/// e.g. generated with `openssl rand -base64 32`.
+#[derive(Clone)]
+pub struct EncryptionKey(Key<Aes256Gcm>);
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    pub fn from_file(path: &Path) -> std::result::Result<Self, CryptoError> {
+        let contents = fs::read_to_string(path).map_err(|source| CryptoError::KeyFileRead {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let invalid = |reason: &str| CryptoError::InvalidKey {
+            path: path.display().to_string(),
+            reason: reason.to_string(),
+        };
+
+        let bytes = STANDARD
+            .decode(contents.trim())
+            .map_err(|_| invalid("expected base64"))?;
+        if bytes.len() != 32 {
+            return Err(invalid("expected 32 bytes"));
+        }
+
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+}
+
+/// Encrypts `reader` into `writer` as a sequence of AES-256-GCM chunks, so
+/// archives of any size can be processed without loading them into memory.
+///
+/// Layout: magic, random nonce prefix, then per chunk a final-chunk flag, the
+/// ciphertext length and the ciphertext. Each nonce is the prefix followed by
+/// the chunk counter, and the flag is authenticated to detect truncation.
+pub fn encrypt_stream<R: Read, W: Write>(
+    key: &EncryptionKey,
+    mut reader: R,
+    mut writer: W,
+) -> std::io::Result<()> {
+    let cipher = Aes256Gcm::new(&key.0);
+    let mut prefix = [0_u8; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&prefix)?;
+
+    let mut current = read_chunk(&mut reader)?;
+    let mut counter = 0_u32;
+    loop {
+        let next = if current.len() == CHUNK_SIZE {
+            read_chunk(&mut reader)?
+        } else {
+            Vec::new()
+        };
+        let is_final = next.is_empty();
+        let flag = [u8::from(is_final)];
+
+        let ciphertext = cipher
+            .encrypt(
+                &chunk_nonce(&prefix, counter),
+                Payload {
+                    msg: &current,
+                    aad: &flag,
+                },
+            )
+            .map_err(|_| std::io::Error::other("encryption failed"))?;
+        writer.write_all(&flag)?;
+        writer.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        writer.write_all(&ciphertext)?;
+
+        if is_final {
+            break;
+        }
+
+        current = next;
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| std::io::Error::other("input too large to encrypt"))?;
+    }
+
+    writer.flush()
+}
+
+pub fn decrypt_stream<R: Read, W: Write>(
+    key: &EncryptionKey,
+    mut reader: R,
+    mut writer: W,
+) -> std::result::Result<(), CryptoError> {
+    let cipher = Aes256Gcm::new(&key.0);
+
+    let mut magic = [0_u8; MAGIC.len()];
+    read_exact_or(&mut reader, &mut magic, CryptoError::NotEncrypted)?;
+    if &magic != MAGIC {
+        return Err(CryptoError::NotEncrypted);
+    }
+
+    let mut prefix = [0_u8; NONCE_PREFIX_LEN];
+    read_exact_or(&mut reader, &mut prefix, CryptoError::Truncated)?;
+
+    let mut counter = 0_u32;
+    loop {
+        let mut header = [0_u8; 5];
+        read_exact_or(&mut reader, &mut header, CryptoError::Truncated)?;
+        let flag = [header[0]];
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if length > CHUNK_SIZE + TAG_LEN {
+            return Err(CryptoError::DecryptionFailed);
+        }
+
+        let mut ciphertext = vec![0_u8; length];
+        read_exact_or(&mut reader, &mut ciphertext, CryptoError::Truncated)?;
+        let plaintext = cipher
+            .decrypt(
+                &chunk_nonce(&prefix, counter),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &flag,
+                },
+            )
+            .map_err(|_| CryptoError::DecryptionFailed)?;
+        writer.write_all(&plaintext)?;
+
+        if flag[0] == 1 {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or(CryptoError::DecryptionFailed)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn encrypt(key: &EncryptionKey, bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len() + 64);
+    encrypt_stream(key, bytes, &mut output).expect("writing to a Vec cannot fail");
+    output
+}
+
+pub fn decrypt(key: &EncryptionKey, bytes: &[u8]) -> std::result::Result<Vec<u8>, CryptoError> {
+    let mut output = Vec::with_capacity(bytes.len());
+    decrypt_stream(key, bytes, &mut output)?;
+    Ok(output)
+}
+
+/// Decrypts `path`, or every `*.enc` file below it when it is a directory
+/// (skipping hidden directories such as `.git`), writing each plaintext next
+/// to its source without the `.enc` suffix. Returns the number of files
+/// restored.
+pub fn decrypt_path(key: &EncryptionKey, path: &Path) -> Result<usize> {
+    if path.is_dir() {
+        let mut restored = 0;
+        for entry in fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            let hidden = entry_path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if entry_path.is_dir() && !hidden
+                || entry_path.to_string_lossy().ends_with(ENCRYPTED_SUFFIX)
+            {
+                restored += decrypt_path(key, &entry_path)?;
+            }
+        }
+        return Ok(restored);
+    }
+
+    let source = path.to_string_lossy();
+    let Some(destination) = source.strip_suffix(ENCRYPTED_SUFFIX) else {
+        return Err(CryptoError::NotEncrypted.into());
+    };
+
+    let mut reader = BufReader::new(File::open(path)?);
+    write_atomic_with(Path::new(destination), |file| {
+        decrypt_stream(key, &mut reader, BufWriter::new(file)).map_err(std::io::Error::other)
+    })?;
+
+    info!(from = %path.display(), to = %destination, "decrypted backup file");
+    Ok(1)
+}
+
+fn read_chunk<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    reader.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn read_exact_or<R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    on_eof: CryptoError,
+) -> std::result::Result<(), CryptoError> {
+    reader
+        .read_exact(buffer)
+        .map_err(|error| match error.kind() {
+            ErrorKind::UnexpectedEof => on_eof,
+            _ => CryptoError::Io(error),
+        })
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32) -> Nonce<U12> {
+    let mut nonce = [0_u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::{Aes256Gcm, KeyInit};
+
+    use super::{decrypt, encrypt, EncryptionKey, CHUNK_SIZE};
+    use crate::error::CryptoError;
+
+    fn key() -> EncryptionKey {
+        EncryptionKey(Aes256Gcm::generate_key(aes_gcm::aead::OsRng))
+    }
+
+    #[test]
+    fn round_trips_multi_chunk_input() {
+        let key = key();
+        let plaintext = (0..CHUNK_SIZE * 2 + 17)
+            .map(|index| index as u8)
+            .collect::<Vec<_>>();
+
+        let ciphertext = encrypt(&key, &plaintext);
+        assert_eq!(decrypt(&key, &ciphertext).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn rejects_truncated_ciphertext() {
+        let key = key();
+        let plaintext = vec![7_u8; CHUNK_SIZE * 2];
+        let ciphertext = encrypt(&key, &plaintext);
+
+        let truncated = &ciphertext[..ciphertext.len() / 2];
+        assert!(matches!(
+            decrypt(&key, truncated),
+            Err(CryptoError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let ciphertext = encrypt(&key(), b"{}");
+        assert!(matches!(
+            decrypt(&key(), &ciphertext),
+            Err(CryptoError::DecryptionFailed)
+        ));
+    }
+}
@@ -14,6 +14,12 @@ pub enum BackupError {
     #[error("authentication error: {0}")]
     Auth(#[from] AuthError),
 
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -28,6 +34,21 @@ pub enum BackupError {
 
     #[error("not implemented yet: {0}")]
     Unimplemented(&'static str),
+
+    #[error("backup interrupted")]
+    Interrupted,
+
+    #[error("{path} holds the clone of repository id {owner}")]
+    CloneDirectoryInUse { path: String, owner: u64 },
+
+    #[error(
+        "not enough free space in {path}: new clones need about {required} bytes, {available} available"
+    )]
+    InsufficientSpace {
+        path: String,
+        required: u64,
+        available: u64,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -38,6 +59,12 @@ pub enum ApiError {
     #[error("unexpected response status {status}: {message}")]
     UnexpectedStatus { status: StatusCode, message: String },
 
+    #[error("failed decoding response body: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("graphql error: {0}")]
+    GraphQl(String),
+
     #[error("request failed after retries: {0}")]
     RetriesExhausted(String),
 }
@@ -56,6 +83,7 @@ impl ApiError {
                     | StatusCode::SERVICE_UNAVAILABLE
                     | StatusCode::GATEWAY_TIMEOUT
             ),
+            Self::Decode(_) | Self::GraphQl(_) => false,
             Self::RetriesExhausted(_) => true,
         }
     }
@@ -76,8 +104,23 @@ pub enum GitError {
         source: std::io::Error,
     },
 
+    #[error("git executable not found on PATH, needed for: {command}")]
+    NotInstalled { command: String },
+
     #[error("invalid git url: {0}")]
     InvalidUrl(String),
+
+    #[error("git command timed out after {seconds}s: {command}")]
+    TimedOut { command: String, seconds: u64 },
+
+    #[error("git command cancelled: {command}")]
+    Cancelled { command: String },
+
+    #[error("gix {operation} failed: {message}")]
+    Native {
+        operation: &'static str,
+        message: String,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -97,4 +140,51 @@ pub enum AuthError {
 
     #[error("failed reading keychain token: {0}")]
     Keychain(String),
+
+    #[error("token from {token} cannot be used for this backup: {missing}")]
+    InsufficientPermissions { token: String, missing: String },
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("invalid storage url: {0}")]
+    InvalidUrl(String),
+
+    #[error("missing storage credentials: {0}")]
+    MissingCredentials(&'static str),
+
+    #[error("storage http error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("unexpected storage response status {status} for '{key}': {message}")]
+    UnexpectedStatus {
+        status: StatusCode,
+        key: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("failed reading encryption key file '{path}': {source}")]
+    KeyFileRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("invalid encryption key file '{path}': {reason}")]
+    InvalidKey { path: String, reason: String },
+
+    #[error("input is not an encrypted backup file")]
+    NotEncrypted,
+
+    #[error("encrypted data is truncated")]
+    Truncated,
+
+    #[error("decryption failed: data is corrupt or the key is wrong")]
+    DecryptionFailed,
+
+    #[error("crypto io error: {0}")]
+    Io(#[from] std::io::Error),
 }
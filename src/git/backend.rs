@@ -0,0 +1,127 @@
+use std::{path::Path, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt};
+
+#[cfg(not(feature = "gix"))]
+use crate::error::BackupError;
+use crate::{
+    config::{CloneMode, GitBackendKind, UpdateStrategy},
+    error::{GitError, Result},
+    git::{progress::TransferStats, subprocess},
+};
+
+/// Clones, fetches and the checks and repairs of the clones a backup keeps,
+/// implemented by spawning the `git` binary or, with the `gix` cargo feature,
+/// natively through gitoxide. Snapshots, resets, submodules, archives and
+/// maintenance always run `git` from [`subprocess`].
+pub trait GitBackend: Send + Sync {
+    /// Clones `url` into `destination` with a working tree, fetching the
+    /// history selected by `mode`.
+    fn clone_repository<'a>(
+        &'a self,
+        url: &'a str,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>>;
+
+    /// Fetches new history into an existing clone made with `mode`, or its
+    /// full history with `unshallow`. With [`UpdateStrategy::FastForward`],
+    /// backends that manage the working tree also fast-forward the checked
+    /// out branch; [`UpdateStrategy::Reset`] leaves local branches alone.
+    fn update_repository<'a>(
+        &'a self,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        unshallow: bool,
+        strategy: UpdateStrategy,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>>;
+
+    /// Lists the branches of `url` as `(object id, ref name)` pairs.
+    fn ls_remote<'a>(
+        &'a self,
+        url: &'a str,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<Vec<(String, String)>, GitError>>;
+
+    /// Points `origin` of the clone at `destination` to `url`.
+    fn set_remote_url<'a>(
+        &'a self,
+        destination: &'a Path,
+        url: &'a str,
+    ) -> BoxFuture<'a, std::result::Result<(), GitError>>;
+
+    /// Checks that the clone at `destination` opens and, when it has
+    /// branches, that the tree of `HEAD` is present. Returns why the clone is
+    /// broken, or `None` when it looks intact; failures that do not point at
+    /// the clone are returned as errors.
+    fn check_clone<'a>(
+        &'a self,
+        destination: &'a Path,
+    ) -> BoxFuture<'a, std::result::Result<Option<String>, GitError>>;
+}
+
+/// Runs the `git` executable found on `PATH`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubprocessBackend;
+
+impl GitBackend for SubprocessBackend {
+    fn clone_repository<'a>(
+        &'a self,
+        url: &'a str,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>> {
+        subprocess::clone_repository(url, destination, mode, timeout).boxed()
+    }
+
+    fn update_repository<'a>(
+        &'a self,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        unshallow: bool,
+        strategy: UpdateStrategy,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>> {
+        subprocess::update_repository(destination, mode, unshallow, strategy, timeout).boxed()
+    }
+
+    fn ls_remote<'a>(
+        &'a self,
+        url: &'a str,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<Vec<(String, String)>, GitError>> {
+        subprocess::ls_remote(url, timeout).boxed()
+    }
+
+    fn set_remote_url<'a>(
+        &'a self,
+        destination: &'a Path,
+        url: &'a str,
+    ) -> BoxFuture<'a, std::result::Result<(), GitError>> {
+        subprocess::set_remote_url(destination, url).boxed()
+    }
+
+    fn check_clone<'a>(
+        &'a self,
+        destination: &'a Path,
+    ) -> BoxFuture<'a, std::result::Result<Option<String>, GitError>> {
+        subprocess::check_clone(destination).boxed()
+    }
+}
+
+/// Returns the backend selected with `--git-backend`, failing when it was not
+/// compiled in.
+pub fn backend(kind: GitBackendKind) -> Result<&'static dyn GitBackend> {
+    match kind {
+        GitBackendKind::Subprocess => Ok(&SubprocessBackend),
+        #[cfg(feature = "gix")]
+        GitBackendKind::Gix => Ok(&super::native::GixBackend),
+        #[cfg(not(feature = "gix"))]
+        GitBackendKind::Gix => Err(BackupError::Config(
+            "the gix git backend requires building with the `gix` cargo feature".to_string(),
+        )),
+    }
+}
@@ -1,2 +1,6 @@
+pub mod backend;
+#[cfg(feature = "gix")]
+pub mod native;
+pub mod progress;
 pub mod subprocess;
 pub mod url;
@@ -0,0 +1,646 @@
+use std::{
+    fs, io,
+    num::NonZeroU32,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use gix::{
+    bstr::BStr,
+    progress::Discard,
+    refs::{transaction::PreviousValue, Target},
+    remote::{
+        fetch::{refs::update::Mode, Shallow, Status},
+        ref_map, Direction,
+    },
+    worktree::stack::state::attributes::Source,
+    ObjectId, Repository,
+};
+use tokio::{task, time::sleep};
+
+use crate::{
+    config::{CloneMode, UpdateStrategy},
+    error::GitError,
+    git::{backend::GitBackend, progress::TransferStats},
+    shutdown,
+};
+
+/// Clones, fetches and checks clones with gitoxide, without a `git`
+/// executable or a process per operation. With
+/// [`UpdateStrategy::FastForward`], the checked out branch and working tree
+/// follow their remote-tracking branch like `git pull --ff-only`. Shallow
+/// clones are supported, partial clone filters are not.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GixBackend;
+
+impl GitBackend for GixBackend {
+    fn clone_repository<'a>(
+        &'a self,
+        url: &'a str,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>> {
+        let (url, destination, mode) = (url.to_string(), destination.to_path_buf(), mode.clone());
+        let operation = format!("clone {url}");
+        run_interruptible(operation, timeout, move |interrupt| {
+            clone(&url, &destination, &mode, interrupt)
+        })
+        .boxed()
+    }
+
+    fn update_repository<'a>(
+        &'a self,
+        destination: &'a Path,
+        mode: &'a CloneMode,
+        unshallow: bool,
+        strategy: UpdateStrategy,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<TransferStats, GitError>> {
+        let (destination, mode) = (destination.to_path_buf(), mode.clone());
+        let operation = format!("fetch in {}", destination.display());
+        run_interruptible(operation, timeout, move |interrupt| {
+            let transfer = fetch(&destination, &mode, unshallow, interrupt)?;
+            if strategy == UpdateStrategy::FastForward {
+                fast_forward(&destination, interrupt)?;
+            }
+            Ok(transfer)
+        })
+        .boxed()
+    }
+
+    fn ls_remote<'a>(
+        &'a self,
+        url: &'a str,
+        timeout: Duration,
+    ) -> BoxFuture<'a, std::result::Result<Vec<(String, String)>, GitError>> {
+        let url = url.to_string();
+        let operation = format!("ls-remote {url}");
+        run_interruptible(operation, timeout, move |_| ls_remote(&url)).boxed()
+    }
+
+    fn set_remote_url<'a>(
+        &'a self,
+        destination: &'a Path,
+        url: &'a str,
+    ) -> BoxFuture<'a, std::result::Result<(), GitError>> {
+        async move { set_remote_url(destination, url) }.boxed()
+    }
+
+    fn check_clone<'a>(
+        &'a self,
+        destination: &'a Path,
+    ) -> BoxFuture<'a, std::result::Result<Option<String>, GitError>> {
+        async move { check_clone(destination) }.boxed()
+    }
+}
+
+fn clone(
+    url: &str,
+    destination: &Path,
+    mode: &CloneMode,
+    interrupt: &AtomicBool,
+) -> std::result::Result<TransferStats, GitError> {
+    if mode.filter.is_some() {
+        return Err(GitError::Native {
+            operation: "clone",
+            message: "partial clone filters are not supported by the gix backend".to_string(),
+        });
+    }
+
+    let mut prepare = gix::prepare_clone(url, destination)
+        .map_err(failed("clone"))?
+        .with_shallow(shallow(mode)?);
+    let (mut checkout, outcome) = prepare
+        .fetch_then_checkout(Discard, interrupt)
+        .map_err(failed("clone"))?;
+    checkout
+        .main_worktree(Discard, interrupt)
+        .map_err(failed("checkout"))?;
+
+    Ok(transfer_stats(&outcome.status))
+}
+
+fn fetch(
+    destination: &Path,
+    mode: &CloneMode,
+    unshallow: bool,
+    interrupt: &AtomicBool,
+) -> std::result::Result<TransferStats, GitError> {
+    let repository = open_for_update(destination)?;
+    let shallow = if unshallow && repository.is_shallow() {
+        Shallow::undo()
+    } else if unshallow {
+        Shallow::NoChange
+    } else {
+        shallow(mode)?
+    };
+    let remote = repository
+        .find_fetch_remote(None)
+        .map_err(failed("fetch"))?;
+    let outcome = remote
+        .connect(Direction::Fetch)
+        .map_err(failed("fetch"))?
+        .prepare_fetch(Discard, ref_map::Options::default())
+        .map_err(failed("fetch"))?
+        .with_shallow(shallow)
+        .receive(Discard, interrupt)
+        .map_err(failed("fetch"))?;
+
+    Ok(transfer_stats(&outcome.status))
+}
+
+/// Opens a clone to update its refs. Reflog entries need a committer, which
+/// minimal containers lack, so a generic one is used when none is configured,
+/// as gitoxide does for clones.
+fn open_for_update(destination: &Path) -> std::result::Result<Repository, GitError> {
+    let mut repository = gix::open(destination).map_err(failed("open"))?;
+    repository
+        .committer_or_set_generic_fallback()
+        .map_err(failed("open"))?;
+    Ok(repository)
+}
+
+/// Moves the checked out branch to its remote-tracking branch and updates the
+/// working tree, failing when upstream rewrote the branch. Shallow clones move
+/// to the fetched tip regardless, since the cut history cannot show that it
+/// descends from the checked out one. A detached `HEAD` or a branch without a
+/// remote-tracking branch is left alone.
+fn fast_forward(destination: &Path, interrupt: &AtomicBool) -> std::result::Result<(), GitError> {
+    let repository = open_for_update(destination)?;
+    let Some(branch) = repository.head_name().map_err(failed("fast-forward"))? else {
+        return Ok(());
+    };
+    let tracking = format!("refs/remotes/origin/{}", branch.shorten());
+    let Some(mut upstream) = repository
+        .try_find_reference(tracking.as_str())
+        .map_err(failed("fast-forward"))?
+    else {
+        return Ok(());
+    };
+    let target = upstream
+        .peel_to_id()
+        .map_err(failed("fast-forward"))?
+        .detach();
+    let current = repository
+        .head()
+        .map_err(failed("fast-forward"))?
+        .id()
+        .map(|id| id.detach());
+    if current == Some(target) {
+        return Ok(());
+    }
+
+    if let (Some(current), false) = (current, repository.is_shallow()) {
+        let base = repository
+            .merge_base(current, target)
+            .map_err(failed("fast-forward"))?;
+        if base != current {
+            return Err(GitError::Native {
+                operation: "fast-forward",
+                message: format!("{branch} diverged from {tracking}, not possible to fast-forward"),
+            });
+        }
+    }
+
+    let tree = commit_tree(&repository, target).map_err(failed("fast-forward"))?;
+    checkout_tree(&repository, tree, interrupt)?;
+
+    let previous = match current {
+        Some(current) => PreviousValue::MustExistAndMatch(Target::Object(current)),
+        None => PreviousValue::MustNotExist,
+    };
+    repository
+        .reference(
+            branch,
+            target,
+            previous,
+            format!("fast-forward to {tracking}"),
+        )
+        .map_err(failed("fast-forward"))?;
+    Ok(())
+}
+
+/// Makes the working tree and index match `tree`, like `git checkout
+/// --force`: tracked files missing from `tree` are removed and the others
+/// overwritten. Untracked files are kept.
+fn checkout_tree(
+    repository: &Repository,
+    tree: ObjectId,
+    interrupt: &AtomicBool,
+) -> std::result::Result<(), GitError> {
+    let Some(workdir) = repository.workdir() else {
+        return Err(GitError::Native {
+            operation: "checkout",
+            message: "the clone has no working tree".to_string(),
+        });
+    };
+
+    let mut index = repository
+        .index_from_tree(&tree)
+        .map_err(failed("checkout"))?;
+    let previous = repository.index_or_empty().map_err(failed("checkout"))?;
+    for entry in previous.entries() {
+        let path = entry.path(&previous);
+        if index.entry_by_path(path).is_none() {
+            remove_tracked_file(workdir, path).map_err(failed("checkout"))?;
+        }
+    }
+
+    let mut options = repository
+        .checkout_options(Source::IdMapping)
+        .map_err(failed("checkout"))?;
+    options.overwrite_existing = true;
+    let objects = repository
+        .objects
+        .clone()
+        .into_arc()
+        .map_err(failed("checkout"))?;
+    let outcome = gix::worktree::state::checkout(
+        &mut index, workdir, objects, &Discard, &Discard, interrupt, options,
+    )
+    .map_err(failed("checkout"))?;
+    if let Some(record) = outcome.errors.first() {
+        return Err(GitError::Native {
+            operation: "checkout",
+            message: format!("{}: {}", record.path, record.error),
+        });
+    }
+    if interrupt.load(Ordering::Relaxed) {
+        return Err(GitError::Native {
+            operation: "checkout",
+            message: "interrupted".to_string(),
+        });
+    }
+
+    index
+        .write(Default::default())
+        .map_err(failed("checkout"))?;
+    Ok(())
+}
+
+/// Removes a file no longer tracked, then its parent directories as long as
+/// they are empty.
+fn remove_tracked_file(workdir: &Path, path: &BStr) -> io::Result<()> {
+    let path = workdir.join(gix::path::from_bstr(path));
+    match fs::remove_file(&path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
+    let mut parent = path.parent();
+    while let Some(directory) = parent.filter(|directory| *directory != workdir) {
+        if fs::remove_dir(directory).is_err() {
+            break;
+        }
+        parent = directory.parent();
+    }
+    Ok(())
+}
+
+/// gitoxide needs a repository to create a remote from, so an empty one is
+/// created in a temporary directory.
+fn ls_remote(url: &str) -> std::result::Result<Vec<(String, String)>, GitError> {
+    static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+    let scratch = std::env::temp_dir().join(format!(
+        "github-backup-rs-ls-remote-{}-{}",
+        std::process::id(),
+        SCRATCH.fetch_add(1, Ordering::Relaxed),
+    ));
+    let result = list_remote_branches(&scratch, url);
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+fn list_remote_branches(
+    scratch: &Path,
+    url: &str,
+) -> std::result::Result<Vec<(String, String)>, GitError> {
+    let repository = gix::init_bare(scratch).map_err(failed("ls-remote"))?;
+    let (ref_map, _) = repository
+        .remote_at(url)
+        .map_err(failed("ls-remote"))?
+        .with_refspecs(
+            Some("+refs/heads/*:refs/remotes/origin/*"),
+            Direction::Fetch,
+        )
+        .map_err(failed("ls-remote"))?
+        .connect(Direction::Fetch)
+        .map_err(failed("ls-remote"))?
+        .ref_map(Discard, ref_map::Options::default())
+        .map_err(failed("ls-remote"))?;
+
+    Ok(ref_map
+        .remote_refs
+        .iter()
+        .filter_map(|reference| match reference.unpack() {
+            (name, Some(oid), _) if name.starts_with(b"refs/heads/") => {
+                Some((oid.to_string(), name.to_string()))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Rewrites `remote.origin.url` in the clone's `.git/config`, replacing the
+/// file through a lock file as git does.
+fn set_remote_url(destination: &Path, url: &str) -> std::result::Result<(), GitError> {
+    let repository = gix::open(destination).map_err(failed("open"))?;
+    let path = repository.git_dir().join("config");
+    let mut config =
+        gix::config::File::from_path_no_includes(path.clone(), gix::config::Source::Local)
+            .map_err(failed("set remote url"))?;
+    config
+        .set_raw_value(&"remote.origin.url", url)
+        .map_err(failed("set remote url"))?;
+
+    let lock = repository.git_dir().join("config.lock");
+    let mut file = fs::File::create(&lock).map_err(|source| GitError::Io { source })?;
+    config
+        .write_to(&mut file)
+        .and_then(|()| fs::rename(&lock, &path))
+        .map_err(|source| GitError::Io { source })
+}
+
+/// Checks the clone the way the subprocess backend does: a repository that
+/// is not recognized as one, a `HEAD` naming no commit and a missing or
+/// unreadable `HEAD` commit or tree point at a broken clone. Other failures,
+/// such as an ownership check, are returned.
+fn check_clone(destination: &Path) -> std::result::Result<Option<String>, GitError> {
+    let repository = match gix::open(destination) {
+        Ok(repository) => repository,
+        Err(error @ gix::open::Error::NotARepository { .. }) => {
+            return Ok(Some(format!("gix cannot open the repository: {error}")));
+        }
+        Err(error) => return Err(failed("open")(error)),
+    };
+
+    let has_branches = repository
+        .references()
+        .map_err(failed("check"))?
+        .local_branches()
+        .map_err(failed("check"))?
+        .next()
+        .is_some();
+    if !has_branches {
+        return Ok(None);
+    }
+
+    let reason = "HEAD does not resolve to a commit";
+    let head = repository.head().map_err(failed("check"))?;
+    let Some(commit) = head.id() else {
+        return Ok(Some(format!("{reason}: HEAD names no commit")));
+    };
+    if !repository.has_object(commit) {
+        return Ok(Some(format!("{reason}: commit {commit} is missing")));
+    }
+    let tree = match commit_tree(&repository, commit.detach()) {
+        Ok(tree) => tree,
+        Err(error) => return Ok(Some(format!("{reason}: {error}"))),
+    };
+    if !repository.has_object(tree) {
+        return Ok(Some(format!("{reason}: tree {tree} is missing")));
+    }
+    Ok(None)
+}
+
+/// The tree of `commit`, or why it cannot be read.
+fn commit_tree(repository: &Repository, commit: ObjectId) -> std::result::Result<ObjectId, String> {
+    let commit = repository
+        .find_commit(commit)
+        .map_err(|error| error.to_string())?;
+    let tree = commit.tree_id().map_err(|error| error.to_string())?;
+    Ok(tree.detach())
+}
+
+/// Maps `--depth` and `--shallow-since` to the gitoxide equivalent.
+fn shallow(mode: &CloneMode) -> std::result::Result<Shallow, GitError> {
+    if let Some(depth) = mode.depth.and_then(NonZeroU32::new) {
+        return Ok(Shallow::DepthAtRemote(depth));
+    }
+
+    match &mode.shallow_since {
+        Some(since) => Ok(Shallow::Since {
+            cutoff: gix::date::parse(since, Some(SystemTime::now()))
+                .map_err(failed("parse date"))?,
+        }),
+        None => Ok(Shallow::NoChange),
+    }
+}
+
+fn transfer_stats(status: &Status) -> TransferStats {
+    let (pack, update_refs) = match status {
+        Status::NoPackReceived { update_refs, .. } => (None, update_refs),
+        Status::Change {
+            write_pack_bundle,
+            update_refs,
+            ..
+        } => (Some(write_pack_bundle), update_refs),
+    };
+
+    let refs_updated = update_refs
+        .updates
+        .iter()
+        .filter(|update| matches!(update.mode, Mode::FastForward | Mode::Forced | Mode::New))
+        .count();
+    TransferStats {
+        objects: pack.map(|pack| u64::from(pack.index.num_objects)),
+        bytes: pack
+            .and_then(|pack| pack.data_path.as_ref())
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len()),
+        refs_updated: Some(refs_updated as u64),
+    }
+}
+
+fn failed<E: std::fmt::Display>(operation: &'static str) -> impl Fn(E) -> GitError {
+    move |error| GitError::Native {
+        operation,
+        message: error.to_string(),
+    }
+}
+
+/// Runs a blocking gitoxide operation on the blocking thread pool. On timeout
+/// or shutdown the interrupt flag is raised and the operation is awaited, so
+/// it no longer writes to the destination when this returns.
+async fn run_interruptible<T, F>(
+    operation: String,
+    timeout: Duration,
+    run: F,
+) -> std::result::Result<T, GitError>
+where
+    T: Send + 'static,
+    F: FnOnce(&AtomicBool) -> std::result::Result<T, GitError> + Send + 'static,
+{
+    if shutdown::is_requested() {
+        return Err(GitError::Cancelled { command: operation });
+    }
+
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let mut task = task::spawn_blocking({
+        let interrupt = Arc::clone(&interrupt);
+        move || run(&interrupt)
+    });
+
+    let stopped = tokio::select! {
+        result = &mut task => return result.map_err(|error| GitError::Io {
+            source: std::io::Error::other(error),
+        })?,
+        () = sleep(timeout) => GitError::TimedOut {
+            command: operation,
+            seconds: timeout.as_secs(),
+        },
+        () = shutdown::cancelled() => GitError::Cancelled { command: operation },
+    };
+
+    interrupt.store(true, Ordering::Relaxed);
+    let _ = task.await;
+    Err(stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test_support::git;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// A bare `remote.git` and a `work` clone pushing to its `main` branch.
+    fn upstream(dir: &Path) -> (PathBuf, PathBuf) {
+        let (remote, work) = (dir.join("remote.git"), dir.join("work"));
+        git(dir, &["init", "--quiet", "--bare", "remote.git"]);
+        git(dir, &["init", "--quiet", "--initial-branch=main", "work"]);
+        git(
+            &work,
+            &["remote", "add", "origin", remote.to_str().unwrap()],
+        );
+        git(&remote, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+        (remote, work)
+    }
+
+    fn commit(work: &Path, file: &str, contents: &str) -> String {
+        fs::write(work.join(file), contents).unwrap();
+        git(work, &["add", "--all"]);
+        git(work, &["commit", "--quiet", "-m", file]);
+        git(work, &["push", "--quiet", "--force", "origin", "HEAD:main"]);
+        git(work, &["rev-parse", "HEAD"])
+    }
+
+    async fn update(clone: &Path) -> std::result::Result<TransferStats, GitError> {
+        GixBackend
+            .update_repository(
+                clone,
+                &CloneMode::default(),
+                false,
+                UpdateStrategy::FastForward,
+                TIMEOUT,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn clones_and_fast_forwards_the_working_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, work) = upstream(dir.path());
+        fs::create_dir(work.join("docs")).unwrap();
+        fs::write(work.join("docs/guide.md"), "guide").unwrap();
+        commit(&work, "README", "first");
+
+        let clone = dir.path().join("clone");
+        let url = remote.to_str().unwrap();
+        GixBackend
+            .clone_repository(url, &clone, &CloneMode::default(), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(clone.join("README")).unwrap(), "first");
+
+        git(&work, &["rm", "--quiet", "-r", "docs"]);
+        let tip = commit(&work, "README", "second");
+        let transfer = update(&clone).await.unwrap();
+        assert!(transfer.refs_updated.unwrap() > 0);
+
+        assert_eq!(git(&clone, &["rev-parse", "HEAD"]), tip);
+        assert_eq!(git(&clone, &["symbolic-ref", "HEAD"]), "refs/heads/main");
+        assert_eq!(fs::read_to_string(clone.join("README")).unwrap(), "second");
+        assert!(!clone.join("docs").exists());
+        assert_eq!(git(&clone, &["status", "--porcelain"]), "");
+        assert_eq!(
+            GixBackend.ls_remote(url, TIMEOUT).await.unwrap(),
+            vec![(tip, "refs/heads/main".to_string())]
+        );
+        assert_eq!(GixBackend.check_clone(&clone).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_fast_forward_rewritten_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, work) = upstream(dir.path());
+        commit(&work, "README", "first");
+        commit(&work, "NOTES", "notes");
+
+        let clone = dir.path().join("clone");
+        GixBackend
+            .clone_repository(
+                remote.to_str().unwrap(),
+                &clone,
+                &CloneMode::default(),
+                TIMEOUT,
+            )
+            .await
+            .unwrap();
+        let cloned = git(&clone, &["rev-parse", "HEAD"]);
+
+        git(&work, &["reset", "--quiet", "--hard", "HEAD~1"]);
+        let rewritten = commit(&work, "README", "rewritten");
+        let error = update(&clone).await.unwrap_err();
+        assert!(
+            error.to_string().contains("not possible to fast-forward"),
+            "{error}"
+        );
+
+        assert_eq!(git(&clone, &["rev-parse", "HEAD"]), cloned);
+        assert_eq!(git(&clone, &["rev-parse", "origin/main"]), rewritten);
+        assert_eq!(fs::read_to_string(clone.join("NOTES")).unwrap(), "notes");
+    }
+
+    #[tokio::test]
+    async fn sets_the_remote_url_and_detects_broken_clones() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, work) = upstream(dir.path());
+        commit(&work, "README", "first");
+
+        let clone = dir.path().join("clone");
+        GixBackend
+            .clone_repository(
+                remote.to_str().unwrap(),
+                &clone,
+                &CloneMode::default(),
+                TIMEOUT,
+            )
+            .await
+            .unwrap();
+        GixBackend
+            .set_remote_url(&clone, "https://github.com/acme/renamed.git")
+            .await
+            .unwrap();
+        assert_eq!(
+            git(&clone, &["remote", "get-url", "origin"]),
+            "https://github.com/acme/renamed.git"
+        );
+
+        let commit = git(&clone, &["rev-parse", "HEAD"]);
+        git(&clone, &["repack", "-a", "-d", "--quiet"]);
+        for pack in fs::read_dir(clone.join(".git/objects/pack")).unwrap() {
+            fs::remove_file(pack.unwrap().path()).unwrap();
+        }
+        let reason = GixBackend.check_clone(&clone).await.unwrap().unwrap();
+        assert!(reason.contains(&commit), "{reason}");
+    }
+}
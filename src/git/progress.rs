@@ -0,0 +1,161 @@
+use serde::Serialize;
+
+/// Transfer statistics of a clone or fetch, read from git's `--progress`
+/// output. Fields are `None` when git did not report them, e.g. no objects
+/// are received when nothing changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TransferStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub objects: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refs_updated: Option<u64>,
+}
+
+impl TransferStats {
+    /// Adds the statistics of another command of the same sync.
+    pub fn add(&mut self, other: Self) {
+        let sum = |left: Option<u64>, right: Option<u64>| match (left, right) {
+            (Some(left), Some(right)) => Some(left + right),
+            (left, right) => left.or(right),
+        };
+        self.objects = sum(self.objects, other.objects);
+        self.bytes = sum(self.bytes, other.bytes);
+        self.refs_updated = sum(self.refs_updated, other.refs_updated);
+    }
+}
+
+/// Parses the stderr of `git clone --progress` or `git fetch --progress`.
+///
+/// Progress lines are redrawn with `\r`, so only the final state of each
+/// line, e.g. `Receiving objects: 100% (6/6), 19.92 KiB | 1.2 MiB/s, done.`,
+/// carries the totals. Ref updates are the `from ... -> to` summary lines
+/// printed by fetch, excluding rejected and unchanged refs.
+pub fn parse_transfer_stats(stderr: &str) -> TransferStats {
+    let mut stats = TransferStats::default();
+    let mut remote_total = None;
+
+    for line in stderr.split(['\r', '\n']) {
+        let transfer = line
+            .strip_prefix("Receiving objects: ")
+            .or_else(|| line.strip_prefix("Unpacking objects: "));
+        if let Some(progress) = transfer {
+            if let Some(objects) = progress_total(progress) {
+                stats.objects = Some(objects);
+            }
+            if let Some(bytes) = progress_bytes(progress) {
+                stats.bytes = Some(bytes);
+            }
+        } else if let Some(total) = line.strip_prefix("remote: Total ") {
+            remote_total = total
+                .split_whitespace()
+                .next()
+                .and_then(|count| count.parse().ok());
+        } else if is_ref_update(line) {
+            *stats.refs_updated.get_or_insert(0) += 1;
+        }
+    }
+
+    stats.objects = stats.objects.or(remote_total);
+    stats
+}
+
+/// `100% (6/6), 19.92 KiB | ...` -> 6
+fn progress_total(progress: &str) -> Option<u64> {
+    let (_, counts) = progress.split_once('(')?;
+    let (counts, _) = counts.split_once(')')?;
+    counts.split_once('/')?.1.parse().ok()
+}
+
+/// `100% (6/6), 19.92 KiB | ...` -> 20398
+fn progress_bytes(progress: &str) -> Option<u64> {
+    let (_, rest) = progress.split_once("), ")?;
+    let size = rest.split(['|', ',']).next()?.trim();
+    let (value, unit) = size.split_once(' ')?;
+    let value = value.parse::<f64>().ok()?;
+    let multiplier = match unit {
+        "bytes" | "byte" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((value * multiplier).round() as u64)
+}
+
+/// Fetch summary lines start with a space and a flag: ` ` fast-forward,
+/// `+` forced update, `*` new ref, `-` pruned and `t` tag update; `=` (up to
+/// date) and `!` (rejected) did not change anything.
+fn is_ref_update(line: &str) -> bool {
+    let mut chars = line.chars();
+    chars.next() == Some(' ')
+        && matches!(chars.next(), Some(' ' | '+' | '*' | '-' | 't'))
+        && line.contains(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_clone_progress() {
+        let stderr = "Cloning into 'repo'...\n\
+            remote: Enumerating objects: 6, done.        \n\
+            remote: Total 6 (delta 1), reused 0 (delta 0), pack-reused 0        \n\
+            Receiving objects:  33% (2/6)\rReceiving objects: 100% (6/6), 19.92 KiB | 19.92 MiB/s, done.\n\
+            Resolving deltas: 100% (1/1), done.\n";
+
+        assert_eq!(
+            parse_transfer_stats(stderr),
+            TransferStats {
+                objects: Some(6),
+                bytes: Some(20398),
+                refs_updated: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_fetch_ref_updates() {
+        let stderr = "remote: Total 3 (delta 0), reused 0 (delta 0), pack-reused 0\n\
+            Unpacking objects: 100% (3/3), 250 bytes | 250.00 KiB/s, done.\n\
+            From https://github.com/owner/repo\n   \
+            1a2b3c4..5d6e7f8  main       -> origin/main\n \
+            * [new branch]      feature    -> origin/feature\n \
+            + 9a8b7c6...1f2e3d4 rewrite    -> origin/rewrite  (forced update)\n \
+            - [deleted]         (none)     -> origin/old\n \
+            = [up to date]      stable     -> origin/stable\n \
+            ! [rejected]        v1         -> v1  (would clobber existing tag)\n";
+
+        assert_eq!(
+            parse_transfer_stats(stderr),
+            TransferStats {
+                objects: Some(3),
+                bytes: Some(250),
+                refs_updated: Some(4),
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_remote_total_and_sums() {
+        let mut stats = parse_transfer_stats("remote: Total 12 (delta 4), reused 0\n");
+        assert_eq!(stats.objects, Some(12));
+        assert_eq!(stats.bytes, None);
+
+        stats.add(TransferStats {
+            objects: Some(3),
+            bytes: Some(100),
+            refs_updated: Some(1),
+        });
+        assert_eq!(
+            stats,
+            TransferStats {
+                objects: Some(15),
+                bytes: Some(100),
+                refs_updated: Some(1),
+            }
+        );
+    }
+}
@@ -1,38 +1,658 @@
-use std::{path::Path, process::Command};
+use std::{path::Path, process::Stdio, time::Duration};
 
-use crate::error::GitError;
+use tokio::{io::AsyncWriteExt, process::Command, time::sleep};
+use tracing::warn;
 
-pub fn clone_repository(url: &str, destination: &Path) -> std::result::Result<(), GitError> {
+use crate::{
+    config::{CloneMode, MaintenanceTask, UpdateStrategy},
+    error::GitError,
+    git::progress::{parse_transfer_stats, TransferStats},
+    shutdown,
+};
+
+/// Limit for commands that only read or update the local repository.
+const LOCAL_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Messages of git failures caused by the environment rather than the clone:
+/// ownership checks, stale lock files, permissions and exhausted resources.
+/// Checked first, since they can mention paths or refs.
+const ENVIRONMENT_ERRORS: &[&str] = &[
+    "dubious ownership",
+    "safe.directory",
+    ".lock",
+    "permission denied",
+    "operation not permitted",
+    "read-only file system",
+    "no space left",
+    "too many open files",
+    "out of memory",
+    "cannot allocate memory",
+];
+
+/// Messages git prints for a missing or invalid `HEAD`, missing or corrupt
+/// objects and damaged packfiles. Git runs with `LC_ALL=C`.
+const CORRUPTION_ERRORS: &[&str] = &[
+    "not a git repository",
+    "invalid head",
+    "bad head",
+    "bad object",
+    "bad sha1",
+    "missing object",
+    "invalid object",
+    "is corrupt",
+    "unable to unpack",
+    "inflate",
+    "packfile",
+    "pack has",
+    "bad packed object",
+    "object file",
+];
+
+/// Whether a `git` executable can be run from `PATH`.
+pub fn is_installed() -> bool {
+    std::process::Command::new("git")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+pub async fn clone_repository(
+    url: &str,
+    destination: &Path,
+    mode: &CloneMode,
+    timeout: Duration,
+) -> std::result::Result<TransferStats, GitError> {
+    let mut args = vec!["clone".to_string(), "--progress".to_string()];
+    args.extend(history_args(mode));
+    if mode.is_shallow() {
+        // Shallow clones default to the default branch only.
+        args.push("--no-single-branch".to_string());
+    }
+    if let Some(filter) = &mode.filter {
+        args.push(format!("--filter={filter}"));
+    }
+    // `url` may come from a repository's `.gitmodules`; never read it as an
+    // option.
+    args.push("--".to_string());
+    args.push(url.to_string());
+    args.push(destination.to_string_lossy().into_owned());
+
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (_, stderr) = run_git_command_capturing(&args, None, None, timeout).await?;
+    Ok(parse_transfer_stats(&stderr))
+}
+
+/// Fetches every remote and, with [`UpdateStrategy::FastForward`],
+/// fast-forwards the checked out branch. Returns the statistics of the fetch,
+/// which transfers all new objects.
+///
+/// Shallow clones are fetched with the depth or date they were cloned with,
+/// and the default branch is then checked out at the fetched tip.
+/// With `unshallow`, the missing history and, for partial clones, the
+/// filtered objects are fetched instead.
+pub async fn update_repository(
+    destination: &Path,
+    mode: &CloneMode,
+    unshallow: bool,
+    strategy: UpdateStrategy,
+    timeout: Duration,
+) -> std::result::Result<TransferStats, GitError> {
+    let mut args = ["fetch", "--all", "--prune", "--progress"]
+        .map(String::from)
+        .to_vec();
+    if unshallow {
+        if destination.join(".git").join("shallow").exists() {
+            args.push("--unshallow".to_string());
+        }
+        if mode.filter.is_some() {
+            clear_partial_clone_filter(destination).await?;
+            args.push("--refetch".to_string());
+        }
+    } else {
+        args.extend(history_args(mode));
+    }
+
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (_, stderr) = run_git_command_capturing(&args, Some(destination), None, timeout).await?;
+    if strategy == UpdateStrategy::FastForward {
+        if destination.join(".git").join("shallow").exists() {
+            // A shallow fetch cuts the history at the new boundary, so the
+            // checked out tip is no ancestor of the fetched one and
+            // `pull --ff-only` would refuse. Move to the fetched tip instead.
+            if let Some(branch) = remote_default_branch(destination, timeout).await? {
+                force_checkout(destination, &branch, &format!("origin/{branch}")).await?;
+            }
+        } else {
+            run_git_command(&["pull", "--ff-only"], Some(destination), timeout).await?;
+        }
+    }
+    Ok(parse_transfer_stats(&stderr))
+}
+
+fn history_args(mode: &CloneMode) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(depth) = mode.depth {
+        args.push(format!("--depth={depth}"));
+    }
+    if let Some(since) = &mode.shallow_since {
+        args.push(format!("--shallow-since={since}"));
+    }
+    args
+}
+
+/// Stops treating `origin` as a promisor remote, so a refetch downloads every
+/// object instead of reapplying the filter.
+async fn clear_partial_clone_filter(destination: &Path) -> std::result::Result<(), GitError> {
     run_git_command(
-        &["clone", url, destination.to_string_lossy().as_ref()],
+        &["config", "--unset-all", "remote.origin.partialclonefilter"],
+        Some(destination),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await?;
+    run_git_command(
+        &["config", "remote.origin.promisor", "false"],
+        Some(destination),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await
+}
+
+/// Packs the objects of a clone with the selected maintenance command.
+pub async fn run_maintenance(
+    destination: &Path,
+    task: MaintenanceTask,
+    timeout: Duration,
+) -> std::result::Result<(), GitError> {
+    let args: &[&str] = match task {
+        MaintenanceTask::Maintenance => &["maintenance", "run", "--quiet"],
+        MaintenanceTask::GcAuto => &["gc", "--auto", "--quiet"],
+        MaintenanceTask::Repack => &["repack", "-a", "-d", "--quiet"],
+    };
+    run_git_command(args, Some(destination), timeout).await
+}
+
+/// Initializes and updates the submodules of a clone, recursively.
+pub async fn update_submodules(
+    destination: &Path,
+    timeout: Duration,
+) -> std::result::Result<(), GitError> {
+    run_git_command(
+        &["submodule", "update", "--init", "--recursive"],
+        Some(destination),
+        timeout,
+    )
+    .await
+}
+
+/// Lists the submodule URLs declared in the clone's `.gitmodules`, as
+/// written there, so relative URLs are not resolved.
+pub async fn list_submodule_urls(destination: &Path) -> std::result::Result<Vec<String>, GitError> {
+    if !destination.join(".gitmodules").is_file() {
+        return Ok(Vec::new());
+    }
+
+    let result = run_git_command_with_output(
+        &[
+            "config",
+            "--file",
+            ".gitmodules",
+            "--get-regexp",
+            r"^submodule\..*\.url$",
+        ],
+        Some(destination),
+        None,
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await;
+    match result {
+        Ok(stdout) => Ok(stdout
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(_, url)| url.trim().to_string())
+            .collect()),
+        // No matching key.
+        Err(GitError::CommandFailed {
+            status: Some(1), ..
+        }) => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Points `origin` of the clone at `url`.
+pub async fn set_remote_url(destination: &Path, url: &str) -> std::result::Result<(), GitError> {
+    run_git_command(
+        &["remote", "set-url", "origin", url],
+        Some(destination),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await
+}
+
+/// Lists the branches of `url` as `(object id, ref name)` pairs.
+pub async fn ls_remote(
+    url: &str,
+    timeout: Duration,
+) -> std::result::Result<Vec<(String, String)>, GitError> {
+    let stdout =
+        run_git_command_with_output(&["ls-remote", "--heads", "--", url], None, None, timeout)
+            .await?;
+    Ok(stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(oid, name)| (oid.to_string(), name.to_string()))
+        .collect())
+}
+
+/// Writes a bundle containing every ref of the clone to `output`. Git writes
+/// the bundle under a lock file and renames it, so `output` is never partial.
+pub async fn create_bundle(
+    destination: &Path,
+    output: &Path,
+    timeout: Duration,
+) -> std::result::Result<(), GitError> {
+    let output = std::path::absolute(output).map_err(|source| GitError::Io { source })?;
+    run_git_command(
+        &[
+            "bundle",
+            "create",
+            output.to_string_lossy().as_ref(),
+            "--all",
+        ],
+        Some(destination),
+        timeout,
+    )
+    .await
+}
+
+/// Lists `(object id, ref name)` pairs for refs matching `patterns`.
+pub async fn list_refs(
+    destination: &Path,
+    patterns: &[&str],
+) -> std::result::Result<Vec<(String, String)>, GitError> {
+    let mut args = vec!["for-each-ref", "--format=%(objectname) %(refname)"];
+    args.extend_from_slice(patterns);
+
+    let stdout =
+        run_git_command_with_output(&args, Some(destination), None, LOCAL_COMMAND_TIMEOUT).await?;
+    Ok(stdout
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(oid, name)| (oid.to_string(), name.to_string()))
+        .collect())
+}
+
+/// Runs `git rev-parse` with `args` and returns its trimmed output.
+pub async fn rev_parse(destination: &Path, args: &[&str]) -> std::result::Result<String, GitError> {
+    let mut rev_parse_args = vec!["rev-parse"];
+    rev_parse_args.extend_from_slice(args);
+
+    let stdout = run_git_command_with_output(
+        &rev_parse_args,
+        Some(destination),
+        None,
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await?;
+    Ok(stdout.trim().to_string())
+}
+
+/// Checks that git can open the clone and, when it has branches, that the
+/// tree of `HEAD` is present. Returns why the clone is broken, or `None` when
+/// it looks intact.
+pub async fn check_clone(destination: &Path) -> std::result::Result<Option<String>, GitError> {
+    if let Err(error) = rev_parse(destination, &["--absolute-git-dir"]).await {
+        return broken_if_failed(error, "git cannot open the repository");
+    }
+
+    let branches = match list_refs(destination, &["refs/heads"]).await {
+        Ok(branches) => branches,
+        Err(error) => return broken_if_failed(error, "refs cannot be read"),
+    };
+    if branches.is_empty() {
+        return Ok(None);
+    }
+
+    let reason = "HEAD does not resolve to a commit";
+    match rev_parse(destination, &["--verify", "--quiet", "HEAD^{tree}"]).await {
+        Ok(_) => Ok(None),
+        // `--quiet` fails silently when an object is missing.
+        Err(GitError::CommandFailed { stderr, .. }) if stderr.is_empty() => {
+            Ok(Some(reason.to_string()))
+        }
+        Err(error) => broken_if_failed(error, reason),
+    }
+}
+
+/// Only a git failure with a corruption message points at a broken clone.
+/// Other failures, such as an ownership check, a stale lock file, a
+/// permission error or a missing `git` executable, are returned so the sync
+/// fails without touching the clone.
+fn broken_if_failed(
+    error: GitError,
+    reason: &str,
+) -> std::result::Result<Option<String>, GitError> {
+    match error {
+        GitError::CommandFailed { stderr, .. } if is_corruption(&stderr) => {
+            Ok(Some(format!("{reason}: {stderr}")))
+        }
+        error => Err(error),
+    }
+}
+
+fn is_corruption(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    !ENVIRONMENT_ERRORS
+        .iter()
+        .any(|message| stderr.contains(message))
+        && CORRUPTION_ERRORS
+            .iter()
+            .any(|message| stderr.contains(message))
+}
+
+/// Returns the remote's default branch after refreshing `origin/HEAD` from
+/// the remote, so a changed default branch is followed. Falls back to the
+/// recorded `origin/HEAD` when the remote cannot tell. `None` when neither
+/// names a branch.
+pub async fn remote_default_branch(
+    destination: &Path,
+    timeout: Duration,
+) -> std::result::Result<Option<String>, GitError> {
+    let result = run_git_command(
+        &["remote", "set-head", "origin", "--auto"],
+        Some(destination),
+        timeout,
+    )
+    .await;
+    match result {
+        Ok(()) => {}
+        Err(GitError::CommandFailed { stderr, .. }) => {
+            warn!(
+                path = %destination.display(),
+                stderr = %stderr,
+                "cannot refresh the remote default branch, using the recorded one",
+            );
+        }
+        Err(error) => return Err(error),
+    }
+    origin_head(destination).await
+}
+
+async fn origin_head(destination: &Path) -> std::result::Result<Option<String>, GitError> {
+    let result = run_git_command_with_output(
+        &["symbolic-ref", "--quiet", "refs/remotes/origin/HEAD"],
+        Some(destination),
         None,
+        LOCAL_COMMAND_TIMEOUT,
     )
+    .await;
+    match result {
+        Ok(stdout) => Ok(stdout
+            .trim()
+            .strip_prefix("refs/remotes/origin/")
+            .map(str::to_string)),
+        // Not a symbolic ref.
+        Err(GitError::CommandFailed {
+            status: Some(1), ..
+        }) => Ok(None),
+        Err(error) => Err(error),
+    }
 }
 
-pub fn update_repository(destination: &Path) -> std::result::Result<(), GitError> {
-    run_git_command(&["fetch", "--all", "--prune"], Some(destination))?;
-    run_git_command(&["pull", "--ff-only"], Some(destination))
+/// Whether commit `ancestor` is reachable from `descendant`.
+pub async fn is_ancestor(
+    destination: &Path,
+    ancestor: &str,
+    descendant: &str,
+) -> std::result::Result<bool, GitError> {
+    let result = run_git_command(
+        &["merge-base", "--is-ancestor", ancestor, descendant],
+        Some(destination),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await;
+    match result {
+        Ok(()) => Ok(true),
+        Err(GitError::CommandFailed {
+            status: Some(1), ..
+        }) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Checks out `branch` reset to `start_point`, discarding local changes to
+/// tracked files.
+pub async fn force_checkout(
+    destination: &Path,
+    branch: &str,
+    start_point: &str,
+) -> std::result::Result<(), GitError> {
+    run_git_command(
+        &["checkout", "--quiet", "--force", "-B", branch, start_point],
+        Some(destination),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await
 }
 
-pub fn ls_remote(url: &str) -> std::result::Result<(), GitError> {
-    run_git_command(&["ls-remote", "--heads", url], None)
+/// Applies `git update-ref --stdin` instructions in a single transaction.
+pub async fn update_refs(
+    destination: &Path,
+    instructions: &str,
+) -> std::result::Result<(), GitError> {
+    run_git_command_with_output(
+        &["update-ref", "--stdin"],
+        Some(destination),
+        Some(instructions.as_bytes()),
+        LOCAL_COMMAND_TIMEOUT,
+    )
+    .await
+    .map(|_| ())
 }
 
-fn run_git_command(args: &[&str], workdir: Option<&Path>) -> std::result::Result<(), GitError> {
+async fn run_git_command(
+    args: &[&str],
+    workdir: Option<&Path>,
+    timeout: Duration,
+) -> std::result::Result<(), GitError> {
+    run_git_command_with_output(args, workdir, None, timeout)
+        .await
+        .map(|_| ())
+}
+
+async fn run_git_command_with_output(
+    args: &[&str],
+    workdir: Option<&Path>,
+    stdin: Option<&[u8]>,
+    timeout: Duration,
+) -> std::result::Result<String, GitError> {
+    run_git_command_capturing(args, workdir, stdin, timeout)
+        .await
+        .map(|(stdout, _)| stdout)
+}
+
+/// Runs git in its own process group so that a timeout or Ctrl-C can stop it
+/// together with the helpers it spawned (remote helpers, ssh, pack-objects).
+/// Returns stdout and stderr of a successful command.
+async fn run_git_command_capturing(
+    args: &[&str],
+    workdir: Option<&Path>,
+    stdin: Option<&[u8]>,
+    timeout: Duration,
+) -> std::result::Result<(String, String), GitError> {
+    let command_line = format!("git {}", args.join(" "));
+    if shutdown::is_requested() {
+        return Err(GitError::Cancelled {
+            command: command_line,
+        });
+    }
+
     let mut command = Command::new("git");
     command.args(args);
     if let Some(workdir) = workdir {
         command.current_dir(workdir);
     }
+    // git runs in a background process group, where reading the terminal
+    // stops it until the timeout. Fail credential, passphrase and host key
+    // prompts instead.
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        // Progress and ref update lines are parsed in English.
+        .env("LC_ALL", "C");
+    if std::env::var_os("GIT_SSH_COMMAND").is_none() && std::env::var_os("GIT_SSH").is_none() {
+        command.env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+    }
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = command.spawn().map_err(|source| {
+        // Spawning also fails with `NotFound` for a missing working directory.
+        if source.kind() == std::io::ErrorKind::NotFound && workdir.is_none_or(Path::is_dir) {
+            GitError::NotInstalled {
+                command: command_line.clone(),
+            }
+        } else {
+            GitError::Io { source }
+        }
+    })?;
+    let pid = child.id();
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input)
+            .await
+            .map_err(|source| GitError::Io { source })?;
+    }
+
+    let output = tokio::select! {
+        output = child.wait_with_output() => output.map_err(|source| GitError::Io { source })?,
+        () = sleep(timeout) => {
+            kill_process_group(pid);
+            return Err(GitError::TimedOut {
+                command: command_line,
+                seconds: timeout.as_secs(),
+            });
+        }
+        () = shutdown::cancelled() => {
+            kill_process_group(pid);
+            return Err(GitError::Cancelled {
+                command: command_line,
+            });
+        }
+    };
 
-    let output = command.output().map_err(|source| GitError::Io { source })?;
     if output.status.success() {
-        return Ok(());
+        return Ok((
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
     }
 
     Err(GitError::CommandFailed {
-        command: format!("git {}", args.join(" ")),
+        command: command_line,
         status: output.status.code(),
-        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        stderr: without_progress_redraws(&String::from_utf8_lossy(&output.stderr)),
     })
 }
+
+/// Keeps only the last state of progress lines redrawn with `\r`.
+fn without_progress_redraws(stderr: &str) -> String {
+    stderr
+        .lines()
+        .filter_map(|line| line.rsplit('\r').next())
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(unix)]
+fn kill_process_group(pid: Option<u32>) {
+    if let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+        // SAFETY: kill(2) has no memory safety requirements; a negative pid
+        // addresses the process group created for this command.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+}
+
+/// Without process groups only the direct child is stopped, which happens
+/// when the dropped `Child` is killed on drop.
+#[cfg(not(unix))]
+fn kill_process_group(_pid: Option<u32>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::git;
+
+    #[test]
+    fn environmental_failures_are_not_corruption() {
+        let failed = |stderr: &str| GitError::CommandFailed {
+            command: "git rev-parse --absolute-git-dir".to_string(),
+            status: Some(128),
+            stderr: stderr.to_string(),
+        };
+
+        for stderr in [
+            "fatal: detected dubious ownership in repository at '/backup/owner/repo'",
+            "fatal: Unable to create '/backup/owner/repo/.git/index.lock': File exists.",
+            "error: cannot open .git/packed-refs: Permission denied",
+            "fatal: unable to access '.git/config': Permission denied",
+            "",
+        ] {
+            assert!(
+                broken_if_failed(failed(stderr), "refs cannot be read").is_err(),
+                "{stderr}"
+            );
+        }
+
+        for stderr in [
+            "fatal: bad object HEAD",
+            "error: packfile .git/objects/pack/pack-1.pack does not match index",
+            "fatal: missing object 0000000000000000000000000000000000000001 for refs/heads/main",
+        ] {
+            assert!(
+                broken_if_failed(failed(stderr), "refs cannot be read")
+                    .unwrap()
+                    .is_some(),
+                "{stderr}"
+            );
+        }
+
+        let missing = GitError::NotInstalled {
+            command: "git rev-parse --absolute-git-dir".to_string(),
+        };
+        assert!(broken_if_failed(missing, "refs cannot be read").is_err());
+    }
+
+    #[tokio::test]
+    async fn lists_remote_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "--quiet", "--initial-branch=main"]);
+        git(
+            dir.path(),
+            &["commit", "--quiet", "--allow-empty", "-m", "initial"],
+        );
+        git(dir.path(), &["tag", "v1"]);
+        let head = git(dir.path(), &["rev-parse", "HEAD"]);
+
+        let url = dir.path().to_str().unwrap();
+        assert_eq!(
+            ls_remote(url, LOCAL_COMMAND_TIMEOUT).await.unwrap(),
+            vec![(head, "refs/heads/main".to_string())]
+        );
+    }
+}
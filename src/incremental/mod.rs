@@ -1,3 +1,5 @@
+pub mod state;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -0,0 +1,51 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::CloneMode, error::Result, io::smart_write::write_json_if_changed};
+
+/// Per-run state persisted between runs, keyed by the stable GitHub
+/// repository id so renames and transfers can be followed.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BackupState {
+    #[serde(default)]
+    pub repositories: BTreeMap<u64, RepositoryState>,
+    /// Start of a run that did not complete. Repositories synced since then
+    /// are skipped until a run completes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryState {
+    pub full_name: String,
+    /// Clone directory relative to the `repositories/` directory.
+    pub path: String,
+    /// Digest of the refs the current archive was built from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_refs_digest: Option<String>,
+    /// History mode the clone was made with, reused by its updates.
+    #[serde(default, skip_serializing_if = "CloneMode::is_full")]
+    pub clone_mode: CloneMode,
+    /// When the clone was last cloned or updated successfully.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<DateTime<Utc>>,
+    /// When maintenance last ran on the clone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintained_at: Option<DateTime<Utc>>,
+}
+
+impl BackupState {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<bool> {
+        Ok(write_json_if_changed(path, self)?)
+    }
+}
@@ -0,0 +1,32 @@
+use std::{io, path::Path};
+
+/// Bytes available to unprivileged writers on the filesystem holding `path`,
+/// measured at its nearest existing ancestor. `None` where not supported.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let existing = path
+        .ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes()).map_err(io::Error::other)?;
+
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stats` is only read after
+    // statvfs reports success.
+    let stats = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stats.assume_init()
+    };
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
@@ -1,2 +1,3 @@
 pub mod atomic_write;
+pub mod disk;
 pub mod smart_write;
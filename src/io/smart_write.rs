@@ -23,11 +23,17 @@ pub fn write_if_changed(path: &Path, bytes: &[u8]) -> std::io::Result<bool> {
 }
 
 pub fn write_json_if_changed<T: Serialize>(path: &Path, value: &T) -> std::io::Result<bool> {
+    let bytes = to_sorted_json_bytes(value)?;
+    write_if_changed(path, &bytes)
+}
+
+/// Serializes `value` as pretty JSON with sorted keys, so unchanged data
+/// always produces identical bytes.
+pub fn to_sorted_json_bytes<T: Serialize>(value: &T) -> std::io::Result<Vec<u8>> {
     let mut json_value = serde_json::to_value(value).map_err(as_io_error)?;
     sort_json(&mut json_value);
 
-    let bytes = to_pretty_json_bytes(&json_value).map_err(as_io_error)?;
-    write_if_changed(path, &bytes)
+    to_pretty_json_bytes(&json_value).map_err(as_io_error)
 }
 
 fn sort_json(value: &mut Value) {
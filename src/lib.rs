@@ -3,11 +3,16 @@ pub mod auth;
 pub mod backup;
 pub mod cli;
 pub mod config;
+pub mod crypto;
 pub mod error;
 pub mod filter;
 pub mod git;
 pub mod incremental;
 pub mod io;
+pub mod shutdown;
+pub mod storage;
+#[cfg(test)]
+mod test_support;
 
 pub use config::BackupConfig;
-pub use error::{ApiError, AuthError, BackupError, GitError, Result};
+pub use error::{ApiError, AuthError, BackupError, CryptoError, GitError, Result, StorageError};
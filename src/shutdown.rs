@@ -0,0 +1,71 @@
+use std::sync::LazyLock;
+
+use tokio::{signal, sync::watch};
+use tracing::warn;
+
+/// Exit status used when a second signal forces the process to stop.
+const FORCED_EXIT_CODE: i32 = 130;
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+/// Asks running work to stop: git commands are killed and no new ones start.
+pub fn request() {
+    SHUTDOWN.send_replace(true);
+}
+
+pub fn is_requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Completes once a shutdown has been requested.
+pub async fn cancelled() {
+    let mut receiver = SHUTDOWN.subscribe();
+    let _ = receiver.wait_for(|requested| *requested).await;
+}
+
+/// Requests a shutdown on the first SIGINT (Ctrl-C) or SIGTERM and exits
+/// immediately on the second one.
+pub fn listen_for_signals() {
+    tokio::spawn(async {
+        let mut signals = Signals::new();
+        if !signals.next().await {
+            return;
+        }
+        warn!(
+            "shutdown requested, stopping running git commands (signal again to exit immediately)"
+        );
+        request();
+
+        if signals.next().await {
+            std::process::exit(FORCED_EXIT_CODE);
+        }
+    });
+}
+
+struct Signals {
+    #[cfg(unix)]
+    terminate: Option<signal::unix::Signal>,
+}
+
+impl Signals {
+    fn new() -> Self {
+        Self {
+            #[cfg(unix)]
+            terminate: signal::unix::signal(signal::unix::SignalKind::terminate()).ok(),
+        }
+    }
+
+    /// Waits for the next SIGINT or SIGTERM. Returns `false` when signals
+    /// cannot be received.
+    async fn next(&mut self) -> bool {
+        #[cfg(unix)]
+        if let Some(terminate) = &mut self.terminate {
+            return tokio::select! {
+                result = signal::ctrl_c() => result.is_ok(),
+                received = terminate.recv() => received.is_some(),
+            };
+        }
+
+        signal::ctrl_c().await.is_ok()
+    }
+}
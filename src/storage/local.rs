@@ -0,0 +1,59 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Result,
+    io::{atomic_write::write_atomic_with, smart_write::write_if_changed},
+};
+
+/// Stores artifacts as files below the output directory.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    pub fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path_for(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(fs::read(path)?))
+    }
+
+    pub fn write_if_changed(&self, key: &str, bytes: &[u8]) -> Result<bool> {
+        Ok(write_if_changed(&self.path_for(key), bytes)?)
+    }
+
+    /// Removes the file for `key`. Returns whether it existed.
+    pub fn remove(&self, key: &str) -> Result<bool> {
+        match fs::remove_file(self.path_for(key)) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn put_file(&self, key: &str, source: &Path) -> Result<()> {
+        let destination = self.path_for(key);
+        if destination == source {
+            return Ok(());
+        }
+
+        let mut reader = File::open(source)?;
+        write_atomic_with(&destination, |file| io::copy(&mut reader, file).map(|_| ()))?;
+        Ok(())
+    }
+}
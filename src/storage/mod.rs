@@ -0,0 +1,199 @@
+pub mod local;
+pub mod s3;
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{BackupConfig, StorageConfig},
+    crypto::{self, EncryptionKey, ENCRYPTED_SUFFIX},
+    error::Result,
+    io::{atomic_write::write_atomic_with, smart_write::to_sorted_json_bytes},
+};
+
+use self::{
+    local::LocalStorage,
+    s3::{S3Credentials, S3Storage},
+};
+
+const STAGING_DIR: &str = ".staging";
+
+#[derive(Debug, Clone)]
+pub enum StorageBackend {
+    Local(LocalStorage),
+    S3(Box<S3Storage>),
+}
+
+/// Destination for metadata files and archives. Keys are `/`-separated paths
+/// relative to the backup root, e.g. `repositories.json`. When an encryption
+/// key is configured every artifact is stored encrypted under `<key>.enc`.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    backend: StorageBackend,
+    encryption: Option<EncryptionKey>,
+    staging_dir: PathBuf,
+}
+
+impl Storage {
+    pub fn from_config(config: &BackupConfig) -> Result<Self> {
+        let backend = match &config.storage {
+            StorageConfig::Local => {
+                StorageBackend::Local(LocalStorage::new(config.output_dir.clone()))
+            }
+            StorageConfig::S3(s3) => StorageBackend::S3(Box::new(S3Storage::new(
+                s3,
+                S3Credentials::from_env()?,
+                Duration::from_secs(config.runtime.request_timeout_seconds),
+            )?)),
+        };
+
+        let encryption = config
+            .encryption_key_file
+            .as_deref()
+            .map(EncryptionKey::from_file)
+            .transpose()?;
+
+        Ok(Self {
+            backend,
+            encryption,
+            staging_dir: config.output_dir.join(STAGING_DIR),
+        })
+    }
+
+    pub fn describe(&self, key: &str) -> String {
+        let key = self.stored_key(key);
+        match &self.backend {
+            StorageBackend::Local(storage) => storage.path_for(&key).display().to_string(),
+            StorageBackend::S3(storage) => storage.describe(&key),
+        }
+    }
+
+    pub async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let stored_key = self.stored_key(key);
+        let bytes = match &self.backend {
+            StorageBackend::Local(storage) => storage.read(&stored_key)?,
+            StorageBackend::S3(storage) => storage.read(&stored_key).await?,
+        };
+
+        match (bytes, &self.encryption) {
+            (Some(bytes), Some(encryption)) => Ok(Some(crypto::decrypt(encryption, &bytes)?)),
+            (bytes, _) => Ok(bytes),
+        }
+    }
+
+    /// Reads the unencrypted `key` left by a run made before an encryption
+    /// key was configured. Returns `None` when encryption is off.
+    pub async fn read_unencrypted(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if self.encryption.is_none() {
+            return Ok(None);
+        }
+
+        match &self.backend {
+            StorageBackend::Local(storage) => storage.read(key),
+            StorageBackend::S3(storage) => storage.read(key).await,
+        }
+    }
+
+    /// Removes the unencrypted `key` left by a run made before an encryption
+    /// key was configured. Does nothing when encryption is off.
+    pub async fn remove_unencrypted(&self, key: &str) -> Result<()> {
+        if self.encryption.is_none() {
+            return Ok(());
+        }
+
+        match &self.backend {
+            StorageBackend::Local(storage) => storage.remove(key).map(|_| ()),
+            StorageBackend::S3(storage) => storage.remove(key).await,
+        }
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        let stored_key = self.stored_key(key);
+        match &self.backend {
+            StorageBackend::Local(storage) => Ok(storage.path_for(&stored_key).exists()),
+            StorageBackend::S3(storage) => storage.exists(&stored_key).await,
+        }
+    }
+
+    pub async fn write_if_changed(&self, key: &str, bytes: &[u8]) -> Result<bool> {
+        let Some(encryption) = &self.encryption else {
+            return self.write_stored_if_changed(key, bytes).await;
+        };
+
+        // Ciphertext differs on every write, so compare the plaintext instead.
+        if self.read(key).await?.as_deref() == Some(bytes) {
+            return Ok(false);
+        }
+
+        let ciphertext = crypto::encrypt(encryption, bytes);
+        self.write_stored_if_changed(&self.stored_key(key), &ciphertext)
+            .await
+    }
+
+    pub async fn write_json_if_changed<T: Serialize>(&self, key: &str, value: &T) -> Result<bool> {
+        let bytes = to_sorted_json_bytes(value)?;
+        self.write_if_changed(key, &bytes).await
+    }
+
+    /// Local path where a large artifact for `key` should be produced before
+    /// handing it to [`Storage::put_file`]. For plain local storage this is
+    /// the final location, so no copy is needed.
+    pub fn staging_path(&self, key: &str) -> PathBuf {
+        match (&self.backend, &self.encryption) {
+            (StorageBackend::Local(storage), None) => storage.path_for(key),
+            _ => self.staging_dir.join(key),
+        }
+    }
+
+    /// Stores a file produced at [`Storage::staging_path`], encrypting it if
+    /// configured, and removes the staged copy once it is stored elsewhere.
+    pub async fn put_file(&self, key: &str, source: &Path) -> Result<()> {
+        let encrypted_source;
+        let upload_source = match &self.encryption {
+            Some(encryption) => {
+                encrypted_source = PathBuf::from(format!("{}{ENCRYPTED_SUFFIX}", source.display()));
+                let mut reader = BufReader::new(File::open(source)?);
+                write_atomic_with(&encrypted_source, |file| {
+                    crypto::encrypt_stream(encryption, &mut reader, BufWriter::new(file))
+                })?;
+                encrypted_source.as_path()
+            }
+            None => source,
+        };
+
+        let stored_key = self.stored_key(key);
+        match &self.backend {
+            StorageBackend::Local(storage) => storage.put_file(&stored_key, upload_source)?,
+            StorageBackend::S3(storage) => storage.put_file(&stored_key, upload_source).await?,
+        }
+
+        if upload_source != source {
+            let _ = fs::remove_file(upload_source);
+        }
+        if source.starts_with(&self.staging_dir) {
+            let _ = fs::remove_file(source);
+        }
+
+        Ok(())
+    }
+
+    fn stored_key(&self, key: &str) -> String {
+        match &self.encryption {
+            Some(_) => format!("{key}{ENCRYPTED_SUFFIX}"),
+            None => key.to_string(),
+        }
+    }
+
+    async fn write_stored_if_changed(&self, stored_key: &str, bytes: &[u8]) -> Result<bool> {
+        match &self.backend {
+            StorageBackend::Local(storage) => storage.write_if_changed(stored_key, bytes),
+            StorageBackend::S3(storage) => storage.write_if_changed(stored_key, bytes).await,
+        }
+    }
+}
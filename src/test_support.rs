@@ -0,0 +1,310 @@
+//! Helpers shared by unit tests: a minimal in-process HTTP server standing in
+//! for the GitHub API, git fixtures, a `secret-tool` stand-in and config
+//! builders.
+
+use std::{
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use clap::Parser;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+use crate::{cli::args::CliArgs, config::BackupConfig};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path including the query string.
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(status: u16, body: &str) -> Self {
+        Self::new(status)
+            .header("content-type", "application/json")
+            .body(body)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Serves every request with `handler` on a random local port, one request
+/// per connection, and records the requests it received.
+pub struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (handler, recorded) = (Arc::clone(&handler), Arc::clone(&recorded));
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let Some(request) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    let response = handler(&request);
+                    let head = request.method == "HEAD";
+                    recorded.lock().unwrap().push(request);
+                    let _ = write_response(stream.get_mut(), &response, head).await;
+                });
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            task,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(stream: &mut R) -> Option<Request> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        let (name, value) = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+        if name == "content-length" {
+            length = value.parse().ok()?;
+        }
+        headers.push((name, value));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.ok()?;
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+async fn write_response<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    response: &Response,
+    head: bool,
+) -> std::io::Result<()> {
+    let mut bytes = format!(
+        "HTTP/1.1 {} Test\r\ncontent-length: {}\r\nconnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        bytes.push_str(&format!("{name}: {value}\r\n"));
+    }
+    bytes.push_str("\r\n");
+
+    stream.write_all(bytes.as_bytes()).await?;
+    if !head {
+        stream.write_all(&response.body).await?;
+    }
+    stream.flush().await
+}
+
+/// Builds a validated config from command line arguments, after the program
+/// name.
+pub fn config(args: &[&str]) -> BackupConfig {
+    let args = CliArgs::parse_from(std::iter::once("github-backup").chain(args.iter().copied()));
+    BackupConfig::from_cli(&args).unwrap()
+}
+
+/// Runs git in `dir` with a fixed identity and no user or system config,
+/// panicking when it fails. Returns the trimmed stdout.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use secret_tool::FakeSecretTool;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod secret_tool {
+    use std::{ffi::OsString, fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use tempfile::TempDir;
+    use tokio::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests that change environment variables.
+    static ENVIRONMENT: Mutex<()> = Mutex::const_new(());
+
+    /// A `secret-tool` stand-in placed first on `PATH`, keeping each item in a
+    /// file named after its service and logging its arguments, with
+    /// `XDG_CONFIG_HOME` pointed at an empty directory. The environment is
+    /// restored on drop.
+    pub struct FakeSecretTool {
+        dir: TempDir,
+        previous: Vec<(&'static str, Option<OsString>)>,
+        _environment: MutexGuard<'static, ()>,
+    }
+
+    impl FakeSecretTool {
+        pub async fn install() -> Self {
+            let environment = ENVIRONMENT.lock().await;
+            let dir = tempfile::tempdir().unwrap();
+            for subdir in ["bin", "items", "config"] {
+                fs::create_dir(dir.path().join(subdir)).unwrap();
+            }
+
+            // Arguments: `lookup service <service> account <account>` or
+            // `store --label <label> service <service> account <account>`.
+            let script = dir.path().join("bin/secret-tool");
+            fs::write(
+                &script,
+                format!(
+                    "#!/bin/sh\n\
+                     echo \"$@\" >> '{dir}/calls'\n\
+                     case \"$1\" in\n\
+                     lookup) cat '{dir}/items/'\"$3\" 2>/dev/null || exit 1 ;;\n\
+                     store) cat > '{dir}/items/'\"$5\" ;;\n\
+                     *) exit 2 ;;\n\
+                     esac\n",
+                    dir = dir.path().display()
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+            let path = std::env::join_paths(std::iter::once(dir.path().join("bin")).chain(
+                std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+            ))
+            .unwrap();
+            let previous = ["PATH", "XDG_CONFIG_HOME"]
+                .into_iter()
+                .map(|name| (name, std::env::var_os(name)))
+                .collect();
+            std::env::set_var("PATH", path);
+            std::env::set_var("XDG_CONFIG_HOME", dir.path().join("config"));
+
+            Self {
+                dir,
+                previous,
+                _environment: environment,
+            }
+        }
+
+        /// The `XDG_CONFIG_HOME` seen while installed.
+        pub fn config_dir(&self) -> PathBuf {
+            self.dir.path().join("config")
+        }
+
+        pub fn item(&self, service: &str) -> Option<String> {
+            fs::read_to_string(self.dir.path().join("items").join(service)).ok()
+        }
+
+        pub fn set_item(&self, service: &str, token: &str) {
+            fs::write(self.dir.path().join("items").join(service), token).unwrap();
+        }
+
+        /// Arguments of every call, one line each.
+        pub fn calls(&self) -> Vec<String> {
+            fs::read_to_string(self.dir.path().join("calls"))
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Drop for FakeSecretTool {
+        fn drop(&mut self) {
+            for (name, value) in &self.previous {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        }
+    }
+}